@@ -1,7 +1,7 @@
 #![allow(unused_imports, unused)]
 use ser::{
     bvc, bvi, conversion::*, instruction::Instruction, machine::*, memory::*, parser::*, stack::*,
-    storage::*, traits::*,
+    state::context::ExecutionEnv, storage::*, traits::*,
 };
 use z3::ast::*;
 
@@ -35,4 +35,20 @@ fn main() {
         .filter(|t| *t.last().unwrap().clone() == Instruction::Revert)
         .collect::<Vec<_>>();
     println!("TRACES WITH REVERTS {:#?}", reverted_traces);
+
+    // Deploy the contract and explore a call to the runtime code its constructor returned,
+    // starting from the storage the constructor wrote
+    let evm = Evm::with_pgm(Parser::with_pgm(COUNTER_WITH_STORAGE_MAPPING_DEPLOY).parse());
+    for deployment in evm.deploy() {
+        let mut runtime = match deployment.call(ExecutionEnv::default()) {
+            Some(runtime) => runtime,
+            None => {
+                println!("INCOMPLETE DEPLOYMENT: {:?}", deployment.incomplete);
+                continue;
+            }
+        };
+        println!("DEPLOYED CODE SIZE: {}", deployment.code().unwrap().bytes.len());
+        let execution = runtime.exec();
+        println!("RUNTIME LEAVES: {}", execution.states.leaves().len());
+    }
 }
\ No newline at end of file
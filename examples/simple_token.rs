@@ -20,6 +20,7 @@ fn main() {
     let mut evm = Evm::with_pgm(pgm);
 
     let execution = evm.exec();
+    let leaves = execution.states.leaves();
     {
        // eprintln!("STATE TREE: {:#?}", execution.states);
         let leaf = execution.states.leaves();
@@ -35,10 +36,10 @@ fn main() {
         .map(|trace| trace.0.iter().map(|t| &t.1).collect::<Vec<_>>())
         .collect::<Vec<_>>();
     eprintln!("traces: {:#?}", traces);
-    let reverted_traces = traces
-        .into_iter()
-        .filter(|t| *t.last().unwrap().clone() == Instruction::Revert)
+    let reverted_leaves = leaves
+        .iter()
+        .filter(|leaf| leaf.val.result.as_ref().map_or(false, |res| res.is_revert()))
         .collect::<Vec<_>>();
-    eprintln!("TRACES WITH REVERTS {:#?}", reverted_traces);
-    //assert_eq!(8, reverted_traces.len());
+    eprintln!("LEAVES WITH REVERTS {:#?}", reverted_leaves);
+    //assert_eq!(8, reverted_leaves.len());
 }
\ No newline at end of file
@@ -0,0 +1,149 @@
+use z3_ext::ast::{Array, Ast, Bool, BV};
+use z3_ext::Sort;
+
+use crate::smt::{ctx, BitVec};
+use crate::storage::Address;
+
+// A transfer of `value` wei, which only happens if `cond` holds. Calls and creations that may
+// fail move value under their success flag.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub from: Address,
+    pub to: Address,
+    pub value: BitVec<32>,
+    pub cond: Bool<'static>,
+}
+
+// Balances of every account along a path, as an SMT array from address to balance so that
+// transfers between symbolic addresses are resolved by the solver
+#[derive(Debug, Clone)]
+pub struct Balances {
+    // Balances when the first transaction on the path starts
+    initial: Array<'static>,
+    // `initial` with every transfer in `transfers` applied
+    inner: Array<'static>,
+    transfers: Vec<Transfer>,
+}
+
+// Every account starts out with an unknown balance
+impl Default for Balances {
+    fn default() -> Self {
+        let ctx = ctx();
+        let initial = Array::new_const(
+            ctx,
+            "balances",
+            &Sort::bitvector(ctx, 160),
+            &Sort::bitvector(ctx, 256),
+        );
+        Self {
+            inner: initial.clone(),
+            initial,
+            transfers: vec![],
+        }
+    }
+}
+
+impl Balances {
+    // Fixes the initial balance of `addr`. Must be called before any transfer.
+    pub fn with_balance(mut self, addr: &Address, balance: &BitVec<32>) -> Self {
+        assert!(self.transfers.is_empty(), "initial balance set after a transfer");
+        self.initial = self.initial.store(addr.as_ref(), balance.as_ref());
+        self.inner = self.initial.clone();
+        self
+    }
+
+    pub fn balance(&self, addr: &Address) -> BitVec<32> {
+        BitVec::with_bv(select(&self.inner, addr).simplify())
+    }
+
+    // Balance of `addr` when the first transaction on the path started
+    pub fn initial(&self, addr: &Address) -> BitVec<32> {
+        BitVec::with_bv(select(&self.initial, addr).simplify())
+    }
+
+    // Whether `addr` holds at least `value`
+    pub fn can_afford(&self, addr: &Address, value: &BitVec<32>) -> Bool<'static> {
+        select(&self.inner, addr).bvuge(value.as_ref()).simplify()
+    }
+
+    // Moves `value` from `from` to `to` if `cond` holds. The balance is not checked; callers
+    // make the transfer conditional on `can_afford` where it matters.
+    pub fn transfer(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        value: &BitVec<32>,
+        cond: &Bool<'static>,
+    ) {
+        let transfer = Transfer {
+            from: from.clone(),
+            to: to.clone(),
+            value: value.clone(),
+            cond: cond.clone(),
+        };
+        self.inner = apply(&self.inner, &transfer);
+        self.transfers.push(transfer);
+    }
+
+    pub fn transfers(&self) -> &[Transfer] {
+        &self.transfers
+    }
+
+    // Drops every transfer after the first `len`
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len >= self.transfers.len() {
+            return;
+        }
+        self.transfers.truncate(len);
+        self.inner = self
+            .transfers
+            .iter()
+            .fold(self.initial.clone(), |arr, transfer| apply(&arr, transfer));
+    }
+}
+
+fn select(arr: &Array<'static>, addr: &Address) -> BV<'static> {
+    arr.select(addr.as_ref()).as_bv().unwrap()
+}
+
+// The debit is stored before the credit is read, so a transfer to the sender itself leaves
+// its balance unchanged
+fn apply(arr: &Array<'static>, transfer: &Transfer) -> Array<'static> {
+    let Transfer {
+        from,
+        to,
+        value,
+        cond,
+    } = transfer;
+    let before = select(arr, from);
+    let debited = cond.ite(&before.bvsub(value.as_ref()), &before).simplify();
+    let arr = arr.store(from.as_ref(), &debited);
+    let before = select(&arr, to);
+    let credited = cond.ite(&before.bvadd(value.as_ref()), &before).simplify();
+    arr.store(to.as_ref(), &credited)
+}
+
+#[test]
+fn test_transfers_move_value() {
+    use crate::bvi;
+    let (alice, bob) = (Address::new_literal(0xa), Address::new_literal(0xb));
+    let mut balances = Balances::default()
+        .with_balance(&alice, &bvi(10))
+        .with_balance(&bob, &bvi(1));
+    let yes = Bool::from_bool(ctx(), true);
+    balances.transfer(&alice, &bob, &bvi(4), &yes);
+    assert_eq!(bvi::<32>(6), balances.balance(&alice));
+    assert_eq!(bvi::<32>(5), balances.balance(&bob));
+    assert_eq!(Some(false), balances.can_afford(&alice, &bvi(7)).as_bool());
+
+    // Sending to oneself changes nothing
+    balances.transfer(&bob, &bob, &bvi(5), &yes);
+    assert_eq!(bvi::<32>(5), balances.balance(&bob));
+
+    balances.transfer(&bob, &alice, &bvi(5), &Bool::from_bool(ctx(), false));
+    assert_eq!(bvi::<32>(5), balances.balance(&bob));
+
+    balances.truncate(0);
+    assert_eq!(bvi::<32>(10), balances.balance(&alice));
+    assert_eq!(bvi::<32>(1), balances.initial(&bob));
+}
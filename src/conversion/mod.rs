@@ -10,6 +10,7 @@ use crate::{
 use rlp::{Decodable, Encodable};
 use ruint::{uint, Bits, FromUintError, ToUintError, Uint};
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
 use z3_ext::{ast::Ast, Context};
 
 impl From<Uint<256, 4>> for BitVec<32> {
@@ -98,6 +99,33 @@ pub fn bitvec_array_to_bv<'ctx>(value: Vec<BitVec<1>>) -> BV<'ctx> {
     }
     bv.extract((bv.get_size() - 8 - 1) as u32, 0).simplify()
 }
+// The big-endian bytes of a bitvector, or None if it does not simplify to a literal
+pub fn concrete_bytes(bv: &BV) -> Option<Vec<u8>> {
+    let size = bv.get_size();
+    (0..size / 8)
+        .map(|i| {
+            let hi = size - 1 - i * 8;
+            bv.extract(hi, hi - 7).simplify().as_u64().map(|b| b as u8)
+        })
+        .collect()
+}
+
+// Memory and code are byte-addressed vectors of single-byte bitvectors
+pub fn concrete_byte_vec(bytes: &[BitVec<1>]) -> Option<Vec<u8>> {
+    bytes
+        .iter()
+        .map(|b| b.as_ref().simplify().as_u64().map(|b| b as u8))
+        .collect()
+}
+
+pub fn keccak256(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(bytes);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.finalize());
+    hash
+}
+
 impl<const SZ: usize> TryFrom<Vec<BitVec<1>>> for BitVec<SZ> {
     type Error = String;
 
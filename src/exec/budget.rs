@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Times a path may jump backwards from the same pc unless the budget says otherwise
+pub const DEFAULT_LOOP_BOUND: usize = 16;
+
+// Limits on how much of the state tree is explored. Paths cut short by a limit are kept as
+// leaves whose result is `EvmResult::Incomplete`. Only loops are bounded by default, so that
+// every exploration terminates; the other limits are off unless set.
+#[derive(Debug, Clone)]
+pub struct ExploreBudget {
+    // Times a path may jump backwards from the same pc
+    pub loop_bound: Option<usize>,
+    // Instructions executed along a single path
+    pub max_path_steps: Option<usize>,
+    // Completed paths, after which exploration stops
+    pub max_paths: Option<usize>,
+    // Nodes in the state tree, after which exploration stops
+    pub max_nodes: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ExploreBudget {
+    fn default() -> Self {
+        Self {
+            loop_bound: Some(DEFAULT_LOOP_BOUND),
+            max_path_steps: None,
+            max_paths: None,
+            max_nodes: None,
+            timeout: None,
+        }
+    }
+}
+
+// The limit that stopped a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetExceeded {
+    LoopBound { pc: usize },
+    PathSteps,
+    Paths,
+    Nodes,
+    Timeout,
+    // Not a limit of the budget: the instruction at `pc` has operands the executor can't
+    // model, such as a symbolic number of bytes to read from memory
+    Unsupported { pc: usize },
+}
+
+// Per-path counters checked against the budget
+#[derive(Debug, Clone, Default)]
+pub struct PathStats {
+    steps: usize,
+    back_edges: HashMap<usize, usize>,
+}
+
+impl PathStats {
+    // Stats of a child reached from `from_pc` by continuing at `to_pc`
+    pub fn step(&self, from_pc: usize, to_pc: usize) -> Self {
+        let mut next = self.clone();
+        next.steps += 1;
+        if to_pc < from_pc {
+            *next.back_edges.entry(from_pc).or_default() += 1;
+        }
+        next
+    }
+}
+
+// Tracks an exploration's progress against its budget
+#[derive(Debug)]
+pub struct BudgetTracker {
+    budget: ExploreBudget,
+    started: Instant,
+    paths: usize,
+    nodes: usize,
+}
+
+impl BudgetTracker {
+    pub fn new(budget: ExploreBudget) -> Self {
+        Self {
+            budget,
+            started: Instant::now(),
+            paths: 0,
+            // The root
+            nodes: 1,
+        }
+    }
+
+    pub fn add_nodes(&mut self, qty: usize) {
+        self.nodes += qty;
+    }
+
+    pub fn add_path(&mut self) {
+        self.paths += 1;
+    }
+
+    // A limit on the whole exploration that has been reached
+    pub fn exhausted(&self) -> Option<BudgetExceeded> {
+        if matches!(self.budget.timeout, Some(timeout) if self.started.elapsed() >= timeout) {
+            return Some(BudgetExceeded::Timeout);
+        }
+        if matches!(self.budget.max_paths, Some(max) if self.paths >= max) {
+            return Some(BudgetExceeded::Paths);
+        }
+        if matches!(self.budget.max_nodes, Some(max) if self.nodes >= max) {
+            return Some(BudgetExceeded::Nodes);
+        }
+        None
+    }
+
+    // The budget of a nested frame entered on a path with `stats`: the limits on the
+    // exploration less what it has used so far, and the steps the path has left
+    pub fn remaining(&self, stats: &PathStats) -> ExploreBudget {
+        ExploreBudget {
+            loop_bound: self.budget.loop_bound,
+            max_path_steps: self
+                .budget
+                .max_path_steps
+                .map(|max| max.saturating_sub(stats.steps)),
+            max_paths: self.budget.max_paths.map(|max| max.saturating_sub(self.paths)),
+            max_nodes: self.budget.max_nodes.map(|max| max.saturating_sub(self.nodes)),
+            timeout: self
+                .budget
+                .timeout
+                .map(|timeout| timeout.saturating_sub(self.started.elapsed())),
+        }
+    }
+
+    // A limit on a single path that `stats` has gone past
+    pub fn path_exceeded(&self, stats: &PathStats) -> Option<BudgetExceeded> {
+        if matches!(self.budget.max_path_steps, Some(max) if stats.steps >= max) {
+            return Some(BudgetExceeded::PathSteps);
+        }
+        let bound = self.budget.loop_bound?;
+        stats
+            .back_edges
+            .iter()
+            .find(|(_, taken)| **taken > bound)
+            .map(|(pc, _)| BudgetExceeded::LoopBound { pc: *pc })
+    }
+}
+
+#[test]
+fn test_loop_bound() {
+    let tracker = BudgetTracker::new(ExploreBudget {
+        loop_bound: Some(1),
+        ..Default::default()
+    });
+    let once = PathStats::default().step(10, 2);
+    assert_eq!(None, tracker.path_exceeded(&once));
+    // Jumping forwards is not a loop
+    let forward = once.step(4, 10);
+    assert_eq!(None, tracker.path_exceeded(&forward));
+    let twice = forward.step(10, 2);
+    assert_eq!(
+        Some(BudgetExceeded::LoopBound { pc: 10 }),
+        tracker.path_exceeded(&twice)
+    );
+}
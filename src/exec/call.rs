@@ -0,0 +1,423 @@
+use z3_ext::ast::{Ast, Bool, BV};
+
+use crate::bvi;
+use crate::conversion::concrete_byte_vec;
+use crate::gas::{self, Charge, GasMeter};
+use crate::instruction::copy_return_data;
+use crate::machine::ExecBranch;
+use crate::memory::Memory;
+use crate::parser::{Parser, Program};
+use crate::record::{
+    CallChange, CallKind, CreateChange, MachineRecord, MemChange, MemOp, ReturnData, StackOp,
+};
+use crate::smt::{ctx, BitVec};
+use crate::state::context::{EvmResult, ExecutionEnv};
+use crate::state::evm::EvmState;
+use crate::storage::{Address, GlobalStorage};
+use crate::traits::MachineComponent;
+
+use super::budget::BudgetExceeded;
+use super::{Execution, ExploreOptions};
+
+// What a nested frame inherits from the exploration of its caller
+pub struct FrameOptions<'ctx> {
+    // The caller's options, with the budget its path has left
+    pub options: ExploreOptions,
+    // The caller's path condition, which holds on every path of the frame
+    pub assumptions: Vec<Bool<'ctx>>,
+}
+
+// Matches the EVM's call depth limit. Calls and creations made at this depth fail.
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+// Most bytes of runtime code a constructor may return (EIP-170)
+pub const MAX_CODE_SIZE: usize = 24576;
+
+// If the code of the target of the call described by `rec` is known, executes the callee as
+// a nested frame and returns one successor of `state` per halted callee path, paired with the
+// callee's path condition. A callee path cut short by the budget leaves the caller's path
+// incomplete, and a call past the depth limit has a single failed successor. Returns None for
+// unknown callees, in which case `rec` itself (with its symbolic success flag and return
+// data) describes the call.
+pub fn nested_call<'ctx>(
+    state: &EvmState,
+    rec: &MachineRecord<32>,
+    env: &ExecutionEnv,
+    frame: &FrameOptions<'ctx>,
+) -> Option<Vec<(EvmState, Option<Bool<'ctx>>)>> {
+    let call = rec.call.as_ref()?;
+    if state.depth >= MAX_CALL_DEPTH {
+        return Some(failed(state, call_record(rec, call, false, vec![])));
+    }
+    let code = code_at(state, env, &call.target)?;
+    let can_afford = match &call.value {
+        Some(value) => state.world.balances.can_afford(&state.address, value),
+        None => Bool::from_bool(ctx(), true),
+    };
+    // A call sending more value than the caller holds fails without running the callee
+    if can_afford.as_bool() == Some(false) {
+        return Some(insufficient_balance(state, &can_afford, || {
+            call_record(rec, call, false, vec![])
+        }));
+    }
+
+    let (gas_limit, stipend) = gas::frame_gas(state, rec);
+    let mut callee = EvmState::with_pgm(code.clone());
+    callee.depth = state.depth + 1;
+    callee.is_static = state.is_static || call.kind == CallKind::StaticCall;
+    callee.hashes = state.hashes.clone();
+    callee.gas = GasMeter::with_limit(gas_limit);
+    callee.memory = Memory::with_model(state.memory.model());
+    // CALLCODE and DELEGATECALL run the callee's code against the caller's account
+    callee.address = match call.kind {
+        CallKind::Call | CallKind::StaticCall => call.target.clone(),
+        CallKind::CallCode | CallKind::DelegateCall => state.address.clone(),
+    };
+    callee.world = entered_world(state, call_record(rec, call, true, vec![]));
+
+    // DELEGATECALL keeps the caller and callvalue of the current frame
+    let callee_env = match call.kind {
+        CallKind::DelegateCall => env.clone(),
+        _ => env
+            .clone()
+            .set_caller(state.address.clone())
+            .set_callvalue(call.value.clone().unwrap_or_default()),
+    }
+    .set_calldata_bytes(call.calldata.clone());
+
+    let successors = run_frame(callee, code, &callee_env, frame)
+        .into_iter()
+        .map(|(leaf, mut conditions)| {
+            if can_afford.as_bool().is_none() {
+                conditions.push(can_afford.clone());
+            }
+            if let Some(incomplete) = incomplete(state, &leaf) {
+                return (incomplete, path_condition(&conditions));
+            }
+            let (success, output) = frame_outcome(&leaf);
+            let mut successor = state.clone();
+            successor.apply_change(call_record(rec, call, success, output));
+            // The callee's path condition may refer to the hashes it took
+            successor.hashes = leaf.hashes.clone();
+            // The unused part of the stipend goes back to the caller
+            let used = leaf.gas.used().as_ref().bvsub(stipend.as_ref());
+            successor.gas.charge(frame_charge(&leaf, used, success));
+            if success {
+                // Events emitted by a frame are discarded if it reverts
+                successor.logs.extend(leaf.logs.iter().cloned());
+                // Storage writes, transfers and creations of a frame are kept if it succeeds
+                successor.adopt_world(leaf.world.clone());
+            }
+            (successor, path_condition(&conditions))
+        })
+        .chain(insufficient_balance(state, &can_afford, || {
+            call_record(rec, call, false, vec![])
+        }))
+        .collect::<Vec<_>>();
+    Some(successors)
+}
+
+// Like `nested_call`, but for CREATE and CREATE2: runs concrete init code as a constructor
+// frame and returns one successor per halted path. On success the returned bytes become the
+// runtime code of the new contract, unless they are rejected or the constructor can't pay to
+// deposit them, which fails the creation. If they are symbolic, the path is left incomplete.
+// Symbolic init code is left to the record's symbolic model.
+pub fn nested_create<'ctx>(
+    state: &EvmState,
+    rec: &MachineRecord<32>,
+    env: &ExecutionEnv,
+    frame: &FrameOptions<'ctx>,
+) -> Option<Vec<(EvmState, Option<Bool<'ctx>>)>> {
+    let create = rec.create.as_ref()?;
+    if state.depth >= MAX_CALL_DEPTH {
+        return Some(failed(state, create_record(rec, create, false, vec![])));
+    }
+    let init_code = concrete_byte_vec(&create.init_code)?;
+
+    let can_afford = state.world.balances.can_afford(&state.address, &create.value);
+    if can_afford.as_bool() == Some(false) {
+        return Some(insufficient_balance(state, &can_afford, || {
+            create_record(rec, create, false, vec![])
+        }));
+    }
+
+    let code = Parser::with_pgm(&hex::encode(init_code)).parse();
+    let (gas_limit, _) = gas::frame_gas(state, rec);
+    let mut constructor = EvmState::with_pgm(code.clone());
+    constructor.depth = state.depth + 1;
+    constructor.is_static = state.is_static;
+    constructor.hashes = state.hashes.clone();
+    constructor.gas = GasMeter::with_limit(gas_limit);
+    constructor.memory = Memory::with_model(state.memory.model());
+    constructor.address = create.address.clone();
+    // The new account exists, without code, while its constructor runs
+    constructor.world = entered_world(state, create_record(rec, create, true, vec![]));
+    let constructor_env = env
+        .clone()
+        .set_caller(state.address.clone())
+        .set_callvalue(create.value.clone())
+        .set_calldata_bytes(vec![]);
+
+    let successors = run_frame(constructor, code, &constructor_env, frame)
+        .into_iter()
+        .flat_map(|(leaf, mut conditions)| {
+            if can_afford.as_bool().is_none() {
+                conditions.push(can_afford.clone());
+            }
+            if let Some(incomplete) = incomplete(state, &leaf) {
+                return vec![(incomplete, path_condition(&conditions))];
+            }
+            let (success, output) = frame_outcome(&leaf);
+            let deployed = match (success, concrete_byte_vec(&output)) {
+                (true, Some(code)) => Some(code),
+                // Code that isn't concrete can't be installed, and the account would look
+                // codeless
+                (true, None) => {
+                    let reason = BudgetExceeded::Unsupported { pc: rec.pc.0 };
+                    let incomplete = halt_incomplete(state, &leaf, reason);
+                    return vec![(incomplete, path_condition(&conditions))];
+                }
+                (false, _) => None,
+            };
+            let rejected = match &deployed {
+                Some(code) => deposit_fails(&leaf, code),
+                None => Bool::from_bool(ctx(), false),
+            };
+            let condition = |cond: &Bool<'static>| {
+                let mut conditions = conditions.clone();
+                if cond.as_bool().is_none() {
+                    conditions.push(cond.clone());
+                }
+                path_condition(&conditions)
+            };
+
+            let mut outcomes = vec![];
+            // Code that can't be deposited fails the creation like an exceptional halt of the
+            // constructor, which uses up all of its gas
+            if rejected.as_bool() != Some(false) {
+                let mut successor = state.clone();
+                successor.apply_change(create_record(rec, create, false, vec![]));
+                successor.hashes = leaf.hashes.clone();
+                let limit = BV::from_u64(ctx(), leaf.gas.limit(), 256);
+                let mut charge = frame_charge(&leaf, limit, false);
+                charge.max = leaf.gas.limit();
+                successor.gas.charge(charge);
+                outcomes.push((successor, condition(&rejected)));
+            }
+            let deposited = rejected.not().simplify();
+            if deposited.as_bool() != Some(false) {
+                let rec = create_record(rec, create, success, output);
+                let installed = rec.create.as_ref().and_then(|create| create.deployed.clone());
+                let mut successor = state.clone();
+                successor.apply_change(rec);
+                successor.hashes = leaf.hashes.clone();
+                successor
+                    .gas
+                    .charge(frame_charge(&leaf, leaf.gas.used().as_ref().clone(), success));
+                if let Some(code) = &deployed {
+                    successor.gas.charge(gas::code_deposit(code.len()));
+                }
+                if success {
+                    successor.logs.extend(leaf.logs.iter().cloned());
+                    let mut world = leaf.world.clone();
+                    world.set_code(&create.address, installed);
+                    successor.adopt_world(world);
+                }
+                outcomes.push((successor, condition(&deposited)));
+            }
+            outcomes
+        })
+        .chain(insufficient_balance(state, &can_afford, || {
+            create_record(rec, create, false, vec![])
+        }))
+        .collect::<Vec<_>>();
+    Some(successors)
+}
+
+// `rec` with the outcome of a callee that halted with `success` and returned `output`: the
+// pushed flag, and the return data copied to memory, follow the callee rather than the
+// record's symbolic model
+fn call_record(
+    rec: &MachineRecord<32>,
+    call: &CallChange,
+    success: bool,
+    output: Vec<BitVec<1>>,
+) -> MachineRecord<32> {
+    let mut rec = rec.clone();
+    if let Some(stack) = rec.stack.as_mut() {
+        // Replace the symbolic success flag pushed by the instruction
+        stack.ops.pop();
+        stack.ops.push(StackOp::Push(bvi(success as i32)));
+    }
+    let output = ReturnData::Bytes(output);
+    // Keep the memory expansion of the call, but write the callee's actual output
+    let mut mem_ops = rec
+        .mem
+        .take()
+        .map(|mem| mem.ops_log)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|op| matches!(op, MemOp::Read { .. }))
+        .collect::<Vec<_>>();
+    mem_ops.extend(copy_return_data(&call.ret_offset, &call.ret_size, &output));
+    rec.mem = Some(MemChange { ops_log: mem_ops });
+    rec.call = Some(CallChange {
+        return_data: output,
+        success: Bool::from_bool(ctx(), success),
+        ..call.clone()
+    });
+    rec.constraints = None;
+    rec
+}
+
+// Like `call_record`, for a constructor. On success its output becomes the deployed code.
+fn create_record(
+    rec: &MachineRecord<32>,
+    create: &CreateChange,
+    success: bool,
+    output: Vec<BitVec<1>>,
+) -> MachineRecord<32> {
+    let mut rec = rec.clone();
+    if let Some(stack) = rec.stack.as_mut() {
+        let pushed: BitVec<32> = if success {
+            create.address.as_ref().zero_ext(96).into()
+        } else {
+            bvi(0)
+        };
+        stack.ops.pop();
+        stack.ops.push(StackOp::Push(pushed));
+    }
+    let deployed = if success {
+        concrete_byte_vec(&output).map(|code| Parser::with_pgm(&hex::encode(code)).parse())
+    } else {
+        None
+    };
+    rec.create = Some(CreateChange {
+        deployed,
+        // Only a failed creation leaves return data behind
+        return_data: ReturnData::Bytes(if success { vec![] } else { output }),
+        success: Bool::from_bool(ctx(), success),
+        ..create.clone()
+    });
+    rec.constraints = None;
+    rec
+}
+
+// The condition under which the code a successful constructor that halted in `leaf` returned
+// can't be deployed. Code over MAX_CODE_SIZE bytes (EIP-170) or starting with 0xEF (EIP-3541)
+// is rejected, and otherwise the constructor must be able to pay for depositing it.
+fn deposit_fails(leaf: &EvmState, code: &[u8]) -> Bool<'static> {
+    if code.len() > MAX_CODE_SIZE || code.first() == Some(&0xef) {
+        return Bool::from_bool(ctx(), true);
+    }
+    leaf.gas.exceeds_limit(&gas::code_deposit(code.len()))
+}
+
+// The only successor of `state`, in which the call or creation of `rec` fails
+fn failed<'ctx>(state: &EvmState, rec: MachineRecord<32>) -> Vec<(EvmState, Option<Bool<'ctx>>)> {
+    let mut successor = state.clone();
+    successor.apply_change(rec);
+    vec![(successor, None)]
+}
+
+// The successor of `state` in which a call or creation fails because the executing account
+// holds less than the value sent, paired with the condition for that. Empty if the account
+// surely holds enough.
+fn insufficient_balance<'ctx>(
+    state: &EvmState,
+    can_afford: &Bool<'static>,
+    failed: impl FnOnce() -> MachineRecord<32>,
+) -> Vec<(EvmState, Option<Bool<'ctx>>)> {
+    let cannot_afford = can_afford.not().simplify();
+    let cond = match cannot_afford.as_bool() {
+        Some(false) => return vec![],
+        Some(true) => None,
+        None => Some(cannot_afford),
+    };
+    let mut successor = state.clone();
+    successor.apply_change(failed());
+    vec![(successor, cond)]
+}
+
+// Code at `addr`. Code in the world, e.g. of contracts created on this path, takes precedence
+// over the environment.
+pub fn code_at(state: &EvmState, env: &ExecutionEnv, addr: &Address) -> Option<Program> {
+    state
+        .world
+        .code(addr)
+        .or_else(|| env.get_contract_code(addr))
+        .cloned()
+}
+
+// The world a nested frame starts in: that of `state` once the record entering the frame,
+// with a successful outcome, has moved the value sent and created the new account
+fn entered_world(state: &EvmState, entering: MachineRecord<32>) -> GlobalStorage {
+    let mut entered = state.clone();
+    entered.apply_change(entering);
+    entered.world
+}
+
+// Runs a nested frame to completion under the options its caller left it and returns its
+// halted paths. A frame with no code halts immediately without executing anything.
+fn run_frame<'ctx>(
+    state: EvmState,
+    code: Program,
+    env: &ExecutionEnv,
+    frame: &FrameOptions<'ctx>,
+) -> Vec<ExecBranch<'ctx>> {
+    if code.bytes.is_empty() {
+        return vec![(state, vec![])];
+    }
+    let mut execution: Execution<'ctx> = Execution::new(state, code)
+        .with_options(frame.options.clone())
+        .with_assumptions(frame.assumptions.clone());
+    execution.run(env);
+    execution.states.leaf_branches()
+}
+
+// The successor of `state` left incomplete because the frame that halted in `leaf` was cut
+// short by the budget. None if the frame halted on its own.
+fn incomplete(state: &EvmState, leaf: &EvmState) -> Option<EvmState> {
+    match leaf.result.as_ref() {
+        Some(EvmResult::Incomplete { reason }) => Some(halt_incomplete(state, leaf, *reason)),
+        _ => None,
+    }
+}
+
+// The successor of `state` left incomplete for `reason` by the frame that halted in `leaf`
+fn halt_incomplete(state: &EvmState, leaf: &EvmState, reason: BudgetExceeded) -> EvmState {
+    let mut successor = state.clone();
+    successor.hashes = leaf.hashes.clone();
+    successor.halt = true;
+    successor.result = Some(EvmResult::Incomplete { reason });
+    successor
+}
+
+fn path_condition<'ctx>(conditions: &[Bool<'ctx>]) -> Option<Bool<'ctx>> {
+    if conditions.is_empty() {
+        None
+    } else {
+        Some(Bool::and(ctx(), conditions.iter().collect::<Vec<_>>().as_slice()))
+    }
+}
+
+// What the caller pays for a halted frame that used `used` gas. Refunds and warm accounts
+// only carry over from frames that succeed.
+fn frame_charge(leaf: &EvmState, used: BV<'static>, success: bool) -> Charge {
+    let mut charge = Charge::free();
+    charge.cost = used.simplify().into();
+    charge.max = leaf.gas.max_used();
+    if success {
+        charge.refund = leaf.gas.refund().clone();
+        charge.accessed = leaf.gas.accessed().to_vec();
+    }
+    charge
+}
+
+// Whether a halted frame succeeded, and the bytes it returned
+fn frame_outcome(leaf: &EvmState) -> (bool, Vec<BitVec<1>>) {
+    match leaf.result.as_ref() {
+        Some(result) => (result.is_success(), result.output().to_vec()),
+        None => (false, vec![]),
+    }
+}
@@ -0,0 +1,113 @@
+use z3_ext::ast::Bool;
+
+use crate::conversion::concrete_byte_vec;
+use crate::machine::{ExecBranch, Evm};
+use crate::parser::{Parser, Program};
+use crate::smt::ctx;
+use crate::state::context::{EvmResult, ExecutionEnv};
+use crate::state::evm::EvmState;
+use crate::storage::Address;
+
+use super::budget::BudgetExceeded;
+use super::sequence::{feasible, run_transaction};
+use super::ExploreOptions;
+
+// A contract deployed by one path of its constructor
+#[derive(Clone)]
+pub struct Deployment {
+    // The constructor's end state. Its world holds the deployed code and the storage the
+    // constructor wrote.
+    pub state: EvmState,
+    // Conditions on the constructor's path, which hold on every later transaction
+    pub conditions: Vec<Bool<'static>>,
+    // Why the deployed code is unknown, if it is. A constructor that returns bytes that aren't
+    // all concrete, e.g. immutables built from symbolic arguments, is `Unsupported` at its
+    // RETURN and leaves nothing to call.
+    pub incomplete: Option<BudgetExceeded>,
+}
+
+impl Deployment {
+    pub fn address(&self) -> &Address {
+        &self.state.address
+    }
+
+    // Runtime code returned by the constructor, unless the deployment is incomplete
+    pub fn code(&self) -> Option<&Program> {
+        self.state.world.code(&self.state.address)
+    }
+
+    // The state exploration of the deployed contract starts from: its code, running against
+    // the storage and balances the constructor left behind
+    pub fn runtime_state(&self) -> Option<EvmState> {
+        self.state.next_transaction()
+    }
+
+    // An Evm exploring the call to the deployed contract described by `env`. The call is the
+    // transaction after the constructor's, so its symbolic inputs are its own. None if the
+    // deployment is incomplete.
+    pub fn call<'ctx>(&self, env: ExecutionEnv<'ctx>) -> Option<Evm<'ctx>> {
+        let state = self.runtime_state()?;
+        let env = env.set_transaction_index(state.transaction);
+        let mut evm = Evm::new(self.code()?.clone(), env);
+        evm.set_init_state(state);
+        if !self.conditions.is_empty() {
+            let conditions = self.conditions.iter().collect::<Vec<_>>();
+            evm.states.path_condition = Some(Bool::and(ctx(), &conditions));
+        }
+        Some(evm)
+    }
+}
+
+// Runs `init_code` as the constructor of the account of `template` and returns its halted
+// paths. On each path that returns concrete bytes, they become the code of the account.
+pub fn run_constructor(
+    template: &EvmState,
+    init_code: Program,
+    env: &ExecutionEnv,
+    options: &ExploreOptions,
+) -> Vec<ExecBranch<'static>> {
+    let mut constructor = template.clone();
+    constructor.pgm = init_code;
+    // The account is new, so its storage starts out zeroed
+    constructor.world.create_account(&constructor.address, None);
+    // Constructor arguments are appended to the init code, so there is no calldata
+    let env = env.clone().set_calldata_bytes(vec![]);
+    let mut paths = run_transaction(constructor, &env, vec![], options);
+    paths.iter_mut().for_each(|(end, _)| install_code(end));
+    paths
+}
+
+// Contracts deployed by the feasible paths among `paths` that returned code. Paths that
+// returned symbolic code are kept as incomplete deployments.
+pub fn deployments(paths: &[ExecBranch<'static>]) -> Vec<Deployment> {
+    paths
+        .iter()
+        .filter(|(end, conditions)| {
+            matches!(end.result, Some(EvmResult::Success { .. })) && feasible(end, conditions)
+        })
+        .filter_map(|(end, conditions)| {
+            let incomplete = match end.world.code(&end.address) {
+                Some(code) if code.bytes.is_empty() => return None,
+                Some(_) => None,
+                None => Some(BudgetExceeded::Unsupported { pc: end.pgm_counter() }),
+            };
+            Some(Deployment {
+                state: end.clone(),
+                conditions: conditions.clone(),
+                incomplete,
+            })
+        })
+        .collect()
+}
+
+// Stores the bytes returned by a successful constructor as the code of its account
+fn install_code(end: &mut EvmState) {
+    let code = match end.result.as_ref() {
+        Some(EvmResult::Success { ret_val }) => concrete_byte_vec(ret_val),
+        _ => None,
+    };
+    if let Some(code) = code {
+        let code = Parser::with_pgm(&hex::encode(code)).parse();
+        end.world.set_code(&end.address, Some(code));
+    }
+}
@@ -0,0 +1,75 @@
+use z3_ext::ast::{Ast, Bool, BV};
+
+use crate::instruction::Instruction;
+use crate::record::{MachineRecord, StackChange, StackOp};
+use crate::smt::ctx;
+use crate::state::evm::EvmState;
+use crate::traits::{MachineComponent, MachineState};
+
+// If `state` is about to JUMP or JUMPI to a destination that does not simplify to a literal,
+// returns one successor per JUMPDEST the destination may equal, paired with the condition for
+// taking it. A successor halting with an invalid jump is added if the destination may be
+// something else, and JUMPI also gets its fallthrough. `can_hold` filters out conditions that
+// are infeasible on the current path.
+pub fn symbolic_jump<'ctx>(
+    state: &EvmState,
+    can_hold: impl Fn(&Bool<'ctx>) -> bool,
+) -> Option<Vec<(EvmState, Option<Bool<'ctx>>)>> {
+    let stack = state.stack();
+    let (dest, cond) = match state.pgm.get(state.pc())? {
+        Instruction::Jump => (stack.peek()?, None),
+        Instruction::JumpI => {
+            let [dest, cond] = stack.peek_top()?;
+            let zero = BV::from_u64(ctx(), 0, 256);
+            (dest, Some(cond.as_ref()._eq(&zero).not()))
+        }
+        _ => return None,
+    };
+    let dest = dest.as_ref().simplify();
+    if dest.as_u64().is_some() {
+        return None;
+    }
+
+    let ctx = ctx();
+    let pc = state.pc();
+    let pop_qty = if cond.is_some() { 2 } else { 1 };
+    let taken = cond.clone().unwrap_or_else(|| Bool::from_bool(ctx, true));
+    let successor = |next_pc: usize, halt: bool| {
+        let mut next = state.clone();
+        next.apply_change(MachineRecord {
+            stack: Some(StackChange::with_ops(vec![StackOp::Pop; pop_qty])),
+            mem: None,
+            pc: (pc, next_pc),
+            constraints: None,
+            halt,
+            storage: None,
+            ..Default::default()
+        });
+        next
+    };
+
+    let mut successors = vec![];
+    let mut not_a_jumpdest = vec![];
+    for target in state.pgm.jumpdests() {
+        let is_target = dest._eq(&BV::from_u64(ctx, target as u64, 256));
+        let jumps_to_target = Bool::and(ctx, &[&taken, &is_target]);
+        if can_hold(&jumps_to_target) {
+            successors.push((successor(target, false), Some(jumps_to_target)));
+        }
+        not_a_jumpdest.push(is_target.not());
+    }
+
+    not_a_jumpdest.push(taken);
+    let invalid = Bool::and(ctx, &not_a_jumpdest.iter().collect::<Vec<_>>());
+    if can_hold(&invalid) {
+        successors.push((successor(pc, true), Some(invalid)));
+    }
+
+    if let Some(cond) = cond {
+        let falls_through = cond.not();
+        if can_hold(&falls_through) {
+            successors.push((successor(pc + 1, false), Some(falls_through)));
+        }
+    }
+    Some(successors)
+}
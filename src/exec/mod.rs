@@ -1,20 +1,57 @@
+pub mod budget;
+pub mod call;
+pub mod deploy;
+pub mod jump;
+pub mod search;
+pub mod sequence;
+
 use uuid::Uuid;
+use z3_ext::ast::Bool;
+use z3_ext::{SatResult, Solver};
 
+use crate::gas;
+use crate::keccak::{self, Hashed};
+use crate::machine::{ExecutionSummary, GasBounds};
 use crate::parser::Program;
-use crate::state::context::ExecutionEnv;
+use crate::smt::ctx;
+use crate::state::context::{EvmResult, ExecutionEnv};
 use crate::state::evm::EvmState;
+use crate::state::revert::RevertReason;
 use crate::state::tree::*;
+use budget::{BudgetExceeded, BudgetTracker, ExploreBudget, PathStats};
+use search::{Frontier, SearchStrategy};
 use crate::{
     instruction::Instruction,
     record::MachineRecord,
     traits::{MachineComponent, MachineInstruction, MachineState},
 };
 
+#[derive(Default, Debug, Clone)]
+pub struct ExploreOptions {
+    // Check each new branch condition against its path and drop branches that cannot be taken
+    pub prune_infeasible: bool,
+    pub strategy: SearchStrategy,
+    pub budget: ExploreBudget,
+}
+
 #[derive(Default, Debug)]
 pub struct Execution<'ctx> {
     changes: Vec<MachineRecord<32>>,
     program: Program,
     pub states: StateTree<'ctx>,
+    strategy: SearchStrategy,
+    budget: ExploreBudget,
+    prune: bool,
+    // Holds one scope per branch condition on the path being stepped. Created by `run`.
+    solver: Option<Solver<'ctx>>,
+    asserted: Vec<(NodeId, Bool<'ctx>)>,
+    // Conditions that hold on every path without being part of any, such as the path
+    // condition of the caller of a nested frame
+    assumptions: Vec<Bool<'ctx>>,
+    // What the budget leaves to nested frames entered by the node being stepped
+    frame_budget: ExploreBudget,
+    // Hashes taken on the path of the node being stepped
+    hashes: Vec<Hashed>,
 }
 
 #[derive(Default, Debug)]
@@ -23,6 +60,7 @@ pub struct StepRecord {
     right_insert: Option<NodeId>,
     halted_left: bool,
     halted_right: bool,
+    forks: Vec<NodeId>,
 }
 
 impl StepRecord {
@@ -52,6 +90,10 @@ impl StepRecord {
         self.right_insert.as_ref()
     }
 
+    pub fn fork_ids(&self) -> &[NodeId] {
+        &self.forks
+    }
+
     pub fn set_left(mut self, left: NodeId) -> Self {
         self.left_insert = Some(left);
         self
@@ -71,6 +113,200 @@ impl<'ctx> Execution<'ctx> {
         }
     }
 
+    pub fn with_options(mut self, options: ExploreOptions) -> Self {
+        self.prune = options.prune_infeasible;
+        self.strategy = options.strategy;
+        self.budget = options.budget;
+        self
+    }
+
+    pub fn with_assumptions(mut self, assumptions: Vec<Bool<'ctx>>) -> Self {
+        self.assumptions = assumptions;
+        self
+    }
+
+    // Steps every path in the state tree until it halts
+    pub fn run(&mut self, env: &ExecutionEnv) {
+        let root_id = self.states.id.clone();
+        // Each pending node is paired with the branch conditions on the path to it, which are
+        // kept asserted on the solver while the node is stepped
+        let assumptions = &self.assumptions;
+        self.solver.get_or_insert_with(|| {
+            // Assumptions are asserted below every scope, so they are never popped
+            let solver = Solver::new(ctx());
+            assumptions.iter().for_each(|cond| solver.assert(cond));
+            solver
+        });
+        let mut budget = BudgetTracker::new(self.budget.clone());
+        let mut pending = Frontier::new(self.strategy);
+        let root_pc = self.states.val.pc();
+        // The root's own condition, if any, holds on every path
+        let root_path = match self.states.path_condition.clone() {
+            Some(cond) => vec![(root_id.clone(), cond)],
+            None => vec![],
+        };
+        pending.push(root_pc, (root_id, root_pc, root_path, PathStats::default()));
+        while let Some((node_id, node_pc, path, stats)) = pending.pop() {
+            // Once the exploration's budget runs out, every pending node is left incomplete
+            if let Some(reason) = budget.exhausted().or_else(|| budget.path_exceeded(&stats)) {
+                self.mark_incomplete(&node_id, reason);
+                continue;
+            }
+            self.sync_solver(&path);
+            self.frame_budget = budget.remaining(&stats);
+            let step = self.step_from_mut(&node_id, env);
+            let children = step
+                .right_id()
+                .into_iter()
+                .chain(step.left_id())
+                .chain(step.fork_ids())
+                .cloned()
+                .collect::<Vec<_>>();
+            if children.is_empty() {
+                budget.add_path();
+            }
+            budget.add_nodes(children.len());
+            for child in children {
+                let tree = self.states.find_by_id(&child).unwrap();
+                let pc = tree.val.pc();
+                let mut child_path = path.clone();
+                if let Some(cond) = tree.path_condition.clone() {
+                    child_path.push((child.clone(), cond));
+                }
+                let child_stats = stats.step(node_pc, pc);
+                pending.push(pc, (child, pc, child_path, child_stats));
+            }
+        }
+    }
+
+    fn mark_incomplete(&mut self, node_id: &NodeId, reason: BudgetExceeded) {
+        if let Some(tree) = self.states.find_by_id_mut(node_id) {
+            if tree.val.can_continue() {
+                tree.val.halt = true;
+                tree.val.result = Some(EvmResult::Incomplete { reason });
+            }
+        }
+    }
+
+    // Paths whose exploration was stopped by the budget, with the limit each one hit
+    pub fn incomplete(&self) -> Vec<(StateTree, BudgetExceeded)> {
+        self.states
+            .leaves()
+            .into_iter()
+            .filter_map(|leaf| {
+                let reason = match &leaf.val.result {
+                    Some(EvmResult::Incomplete { reason }) => *reason,
+                    _ => return None,
+                };
+                Some((leaf, reason))
+            })
+            .collect()
+    }
+
+    // Makes the solver's scopes match `path`, popping the conditions of nodes no longer on it
+    fn sync_solver(&mut self, path: &[(NodeId, Bool<'ctx>)]) {
+        let solver = match self.solver.as_ref() {
+            Some(solver) => solver,
+            None => return,
+        };
+        let common = self
+            .asserted
+            .iter()
+            .zip(path)
+            .take_while(|((asserted, _), (id, _))| asserted == id)
+            .count();
+        solver.pop((self.asserted.len() - common) as u32);
+        self.asserted.truncate(common);
+        for (id, cond) in &path[common..] {
+            solver.push();
+            solver.assert(cond);
+            self.asserted.push((id.clone(), cond.clone()));
+        }
+    }
+
+    // What a nested frame entered from the node being stepped inherits: the options of this
+    // execution, the budget the node's path has left, and every condition known to hold on it
+    fn frame_options(&self) -> call::FrameOptions<'ctx> {
+        call::FrameOptions {
+            options: ExploreOptions {
+                prune_infeasible: self.prune,
+                strategy: self.strategy,
+                budget: self.frame_budget.clone(),
+            },
+            assumptions: self
+                .assumptions
+                .iter()
+                .chain(self.asserted.iter().map(|(_, cond)| cond))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // Whether a new branch under `cond` is kept. Without pruning every branch is.
+    fn is_feasible(&self, cond: &Bool<'ctx>) -> bool {
+        !self.prune || self.can_hold(cond)
+    }
+
+    // Whether `cond` can hold on the path currently asserted on the solver
+    fn can_hold(&self, cond: &Bool<'ctx>) -> bool {
+        let solver = match self.solver.as_ref() {
+            Some(solver) => solver,
+            None => return true,
+        };
+        solver.push();
+        solver.assert(cond);
+        keccak::axioms(&self.hashes).iter().for_each(|axiom| solver.assert(axiom));
+        let result = solver.check();
+        solver.pop(1);
+        result != SatResult::Unsat
+    }
+
+    pub fn summary(&self) -> ExecutionSummary<'ctx> {
+        ExecutionSummary::with_branches(self.states.leaf_branches())
+    }
+
+    // Gas bounds of every function selector found in the program's dispatcher
+    pub fn gas_bounds(&self) -> Vec<GasBounds> {
+        let summary = self.summary();
+        self.program
+            .selectors()
+            .into_iter()
+            .filter_map(|selector| summary.gas_bounds(selector))
+            .collect()
+    }
+
+    // Every reverted path, with its decoded revert payload
+    pub fn reverts(&self) -> Vec<(StateTree, RevertReason)> {
+        self.states
+            .leaves()
+            .into_iter()
+            .filter_map(|leaf| {
+                let reason = leaf.val.result.as_ref()?.revert_reason()?;
+                Some((leaf, reason))
+            })
+            .collect()
+    }
+
+    // Reverted paths whose payload matches `pred`, e.g. every arithmetic overflow panic
+    pub fn reverts_where(
+        &self,
+        pred: impl Fn(&RevertReason) -> bool,
+    ) -> Vec<(StateTree, RevertReason)> {
+        self.reverts()
+            .into_iter()
+            .filter(|(_, reason)| pred(reason))
+            .collect()
+    }
+
+    // Paths that ran out of gas
+    pub fn out_of_gas(&self) -> Vec<StateTree> {
+        self.states
+            .leaves()
+            .into_iter()
+            .filter(|leaf| matches!(leaf.val.result, Some(EvmResult::OutOfGas)))
+            .collect()
+    }
+
     // Returns the StepRecord AND updates the Exec state tree
     pub fn step_mut(&mut self, env: &ExecutionEnv) -> StepRecord {
         // bool returns if there is a branch
@@ -78,15 +314,24 @@ impl<'ctx> Execution<'ctx> {
         let mut curr_state = self.states.val.clone();
         let curr_inst = curr_state.curr_instruction();
         let curr_pc = curr_state.pc();
-        let change_rec = curr_inst.exec(&curr_state, &env);
+        // Only gas costs that are known to exceed the limit are checked here
+        if gas::out_of_gas(&curr_state, env).as_bool() == Some(true) {
+            curr_state.halt_exceptionally(EvmResult::OutOfGas);
+            let left_tree = StateTree::from((curr_state, None));
+            let left_id = self.states.insert_left_of(left_tree, curr_state_id.id());
+            return StepRecord::new(false, true).set_left(left_id);
+        }
+        let change_rec = match curr_inst.exec(&curr_state, &env) {
+            Ok(rec) => rec,
+            Err(err) => {
+                curr_state.halt_exceptionally(err.into());
+                let left_tree = StateTree::from((curr_state, None));
+                let left_id = self.states.insert_left_of(left_tree, curr_state_id.id());
+                return StepRecord::new(false, true).set_left(left_id);
+            }
+        };
         //eprintln!("CHANGE REC IN EXEC: {:#?}", change_rec);
         let is_branch = change_rec.constraints.is_some();
-        if is_branch {
-            eprintln!(
-                "IS BRANCH HERE:\nChange record {:#?}\nCurr_state pc: {}",
-                change_rec, curr_pc
-            );
-        }
         curr_state.apply_change(change_rec.clone());
         let mut report = StepRecord::new(false, change_rec.halt);
         if is_branch {
@@ -126,7 +371,14 @@ impl<'ctx> Execution<'ctx> {
         let mut curr_state = self.states.val.clone();
         let curr_inst = curr_state.curr_instruction();
         let curr_pc = curr_state.pc();
-        let change_rec = curr_inst.exec(&curr_state, &env);
+        let change_rec = match curr_inst.exec(&curr_state, &env) {
+            Ok(rec) => rec,
+            Err(err) => {
+                curr_state.halt_exceptionally(err.into());
+                let left_tree = StateTree::from((curr_state, None));
+                return StepRecord::new(false, true).set_left(left_tree.id.clone());
+            }
+        };
 
         let is_branch = change_rec.constraints.is_some();
         curr_state.apply_change(change_rec.clone());
@@ -156,36 +408,138 @@ impl<'ctx> Execution<'ctx> {
         }
     }
 
+    // Halts the node's state with `result` where `cond` holds. If `cond` may or may not hold,
+    // forks into a halted leaf and a copy of the state that is stepped again under its
+    // negation. None if `cond` can't hold, in which case the node is stepped as usual.
+    fn halt_where(
+        &mut self,
+        state: &EvmState,
+        node_id: &NodeId,
+        cond: Bool<'ctx>,
+        result: EvmResult,
+    ) -> Option<StepRecord> {
+        match cond.as_bool() {
+            Some(true) => {
+                let mut halted = state.clone();
+                halted.halt_exceptionally(result);
+                let left_tree = StateTree::from((halted, None));
+                let left_id = self.states.insert_left_of(left_tree, node_id.id());
+                Some(StepRecord::new(false, true).set_left(left_id))
+            }
+            None if self.solver.is_some() && self.can_hold(&cond) => {
+                let mut report = StepRecord::new(false, false);
+                let mut halted = state.clone();
+                halted.halt_exceptionally(result);
+                let negated = cond.not();
+                let mut successors = vec![(halted, Some(cond))];
+                if self.can_hold(&negated) {
+                    successors.push((state.clone(), Some(negated)));
+                }
+                successors.into_iter().for_each(|successor| {
+                    let fork_ref = self
+                        .states
+                        .insert_fork_of(StateTree::from(successor), node_id.id());
+                    report.forks.push(fork_ref);
+                });
+                Some(report)
+            }
+            _ => None,
+        }
+    }
+
     pub fn step_from_mut(&mut self, node_id: &NodeId, env: &ExecutionEnv) -> StepRecord {
         let curr_state_id = node_id.clone();
         let mut curr_state_tree = self.states.find_by_id(node_id).unwrap().clone();
-        eprintln!("CURR STATE:{:#?} ", curr_state_tree);
         let mut curr_state = &mut curr_state_tree.val;
         if !curr_state.can_continue() {
             return StepRecord::new(true, true);
         }
+        self.hashes = curr_state.hashes.clone();
+
+        // A path that may not afford the instruction forks into an out-of-gas leaf and a copy of
+        // the current state that is stepped again under the condition that it can. The
+        // instruction is priced before it is executed, so that nothing is built for it first,
+        // and before jumps fork on symbolic targets.
+        let out_of_gas = gas::out_of_gas(curr_state, env);
+        if let Some(report) = self.halt_where(curr_state, node_id, out_of_gas, EvmResult::OutOfGas) {
+            return report;
+        }
+
+        // Symbolic jump targets fork once per destination the solver can't rule out
+        if let Some(successors) = jump::symbolic_jump(curr_state, |cond| self.can_hold(cond)) {
+            let mut report = StepRecord::new(false, false);
+            successors.into_iter().for_each(|successor| {
+                let fork_ref = self
+                    .states
+                    .insert_fork_of(StateTree::from(successor), node_id.id());
+                report.forks.push(fork_ref);
+            });
+            return report;
+        }
 
         let curr_inst = curr_state.curr_instruction();
         let curr_pc = curr_state.pc();
-        //eprintln!("CURR STATE IN STEP FROM MUT: {:#?}", curr_state);
-        
-        let change_rec = curr_inst.exec(&curr_state, &env);
-       
-        eprintln!("CHANGE REC IN STEP: {:#?}", change_rec);
-        eprintln!("Instruction: {:#?} STACK: {:#?}, ",curr_inst, curr_state.stack());
+
+        let change_rec = match curr_inst.exec(&curr_state, &env) {
+            Ok(rec) => rec,
+            // Underflows and overflows end the path in an exceptional-halt leaf, and operands
+            // that can't be modelled in an incomplete one
+            Err(err) => {
+                curr_state.halt_exceptionally(err.into());
+                let left_tree = StateTree::from((curr_state.clone(), None));
+                let left_id = self.states.insert_left_of(left_tree, curr_state_id.id());
+                return StepRecord::new(false, true).set_left(left_id);
+            }
+        };
+        // Conditions checked before the record is applied may refer to the hashes it takes
+        self.hashes.extend(change_rec.hashes.iter().cloned());
+
+        // Copies of symbolic length are unrolled up to SYMBOLIC_COPY_BOUND bytes, so
+        // paths on which the length may exceed it are left incomplete
+        let unbounded = curr_inst.beyond_copy_bound(curr_state, env);
+        let incomplete = EvmResult::Incomplete {
+            reason: BudgetExceeded::Unsupported { pc: curr_pc },
+        };
+        if let Some(report) = self.halt_where(curr_state, node_id, unbounded, incomplete) {
+            return report;
+        }
+
+        let nested = (change_rec.call.is_some() || change_rec.create.is_some())
+            .then(|| self.frame_options())
+            .and_then(|frame| {
+                call::nested_call(curr_state, &change_rec, env, &frame)
+                    .or_else(|| call::nested_create(curr_state, &change_rec, env, &frame))
+            });
+        if let Some(successors) = nested {
+            let mut report = StepRecord::new(false, false);
+            successors.into_iter().for_each(|successor| {
+                if let Some(cond) = &successor.1 {
+                    if !self.is_feasible(cond) {
+                        return;
+                    }
+                }
+                let fork_ref = self
+                    .states
+                    .insert_fork_of(StateTree::from(successor), node_id.id());
+                report.forks.push(fork_ref);
+            });
+            return report;
+        }
         
 
         let is_branch = change_rec.constraints.is_some();
+        // The fallthrough of a JUMPI must not inherit the taken branch's invalid-jump halt
+        let fallthrough = (is_branch && change_rec.halt).then(|| {
+            let mut fallthrough = curr_state.clone();
+            fallthrough.apply_change(MachineRecord {
+                pc: (curr_pc, curr_pc + 1),
+                constraints: None,
+                halt: false,
+                ..change_rec.clone()
+            });
+            fallthrough
+        });
         curr_state.apply_change(change_rec.clone());
-        if is_branch {
-            eprintln!(
-                "IS BRANCH HERE:\nChange record {:#?}\nCurr_state pc: {}",
-                change_rec, curr_pc
-            );
-        }
-        if change_rec.halt {
-            eprintln!("Halt occurred here: {:#?}", change_rec);
-        }
         let curr_state = curr_state;
         let mut report = StepRecord::new(false, change_rec.halt);
         // assert_eq!(change_rec.halt, curr_state.halt);
@@ -193,20 +547,29 @@ impl<'ctx> Execution<'ctx> {
             // then curr_state.apply generated the right branching state; thus, a state tree w/
             // an additional constraint
             // and left tree (by convention left path represents straight line execution) is the negation of such constraint
-            let mut left_state = curr_state.clone();
-            let right_tree =
-                StateTree::from((left_state.clone(), change_rec.constraints.clone().unwrap()));
-            left_state.set_pc(curr_pc + 1);
+            let right_cond = change_rec.constraints.unwrap();
+            let left_cond = right_cond.not();
+            let right_tree = StateTree::from((curr_state.clone(), right_cond.clone()));
+            let left_state = fallthrough.unwrap_or_else(|| {
+                let mut left_state = curr_state.clone();
+                left_state.set_pc(curr_pc + 1);
+                left_state
+            });
             report.halted_left = left_state.halt;
 
-            let left_tree = StateTree::from((left_state, change_rec.constraints.unwrap().not()));
+            let left_tree = StateTree::from((left_state, left_cond.clone()));
 
-            let left_tree_ref = self.states.insert_left_of(left_tree, node_id.id());
-            let right_tree_ref = self.states.insert_right_of(right_tree, node_id.id());
             // curr_state_tree.left = Some(Box::new(left_tree));
             // curr_state_tree.right = Some(Box::new(right_tree));
-
-            report.set_left(left_tree_ref).set_right(right_tree_ref)
+            if self.is_feasible(&left_cond) {
+                let left_tree_ref = self.states.insert_left_of(left_tree, node_id.id());
+                report = report.set_left(left_tree_ref);
+            }
+            if self.is_feasible(&right_cond) {
+                let right_tree_ref = self.states.insert_right_of(right_tree, node_id.id());
+                report = report.set_right(right_tree_ref);
+            }
+            report
         } else {
             let left_tree = StateTree::from((curr_state.clone(), None));
             let left_tree_ref = self.states.insert_left_of(left_tree, curr_state_id.id());
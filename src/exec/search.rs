@@ -0,0 +1,98 @@
+use std::collections::{HashSet, VecDeque};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Order in which pending nodes of the state tree are stepped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchStrategy {
+    // Follow one path to its end before backtracking; exhaustive with little memory
+    #[default]
+    DepthFirst,
+    // Step every pending path once per round; reaches shallow states first
+    BreadthFirst,
+    // Pick a pending node uniformly at random. The seed makes runs reproducible.
+    Random { seed: u64 },
+    // Prefer nodes whose pc has not been stepped yet, falling back to depth first
+    Coverage,
+}
+
+// The pending nodes of an exploration, each tagged with the pc it will execute next
+#[derive(Debug)]
+pub struct Frontier<T> {
+    strategy: SearchStrategy,
+    pending: VecDeque<(usize, T)>,
+    visited: HashSet<usize>,
+    rng: Option<StdRng>,
+}
+
+impl<T> Frontier<T> {
+    pub fn new(strategy: SearchStrategy) -> Self {
+        let rng = match strategy {
+            SearchStrategy::Random { seed } => Some(StdRng::seed_from_u64(seed)),
+            _ => None,
+        };
+        Self {
+            strategy,
+            pending: VecDeque::new(),
+            visited: HashSet::new(),
+            rng,
+        }
+    }
+
+    pub fn push(&mut self, pc: usize, item: T) {
+        self.pending.push_back((pc, item));
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let idx = match self.strategy {
+            SearchStrategy::DepthFirst => self.pending.len().checked_sub(1)?,
+            SearchStrategy::BreadthFirst => 0,
+            SearchStrategy::Random { .. } => {
+                if self.pending.is_empty() {
+                    return None;
+                }
+                let len = self.pending.len();
+                self.rng.as_mut().unwrap().gen_range(0..len)
+            }
+            SearchStrategy::Coverage => self
+                .pending
+                .iter()
+                .rposition(|(pc, _)| !self.visited.contains(pc))
+                .or_else(|| self.pending.len().checked_sub(1))?,
+        };
+        let (pc, item) = self.pending.remove(idx)?;
+        self.visited.insert(pc);
+        Some(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[test]
+fn test_frontier_order() {
+    let mut dfs = Frontier::new(SearchStrategy::DepthFirst);
+    let mut bfs = Frontier::new(SearchStrategy::BreadthFirst);
+    for (pc, item) in [(0, "a"), (1, "b"), (2, "c")] {
+        dfs.push(pc, item);
+        bfs.push(pc, item);
+    }
+    assert_eq!(Some("c"), dfs.pop());
+    assert_eq!(Some("a"), bfs.pop());
+
+    // "b" is the newest node at an unvisited pc
+    let mut coverage = Frontier::new(SearchStrategy::Coverage);
+    coverage.push(0, "a");
+    assert_eq!(Some("a"), coverage.pop());
+    coverage.push(1, "b");
+    coverage.push(0, "c");
+    assert_eq!(Some("b"), coverage.pop());
+    assert_eq!(Some("c"), coverage.pop());
+    assert_eq!(None, coverage.pop());
+}
@@ -0,0 +1,113 @@
+use z3_ext::ast::Bool;
+use z3_ext::{SatResult, Solver};
+
+use crate::keccak;
+use crate::machine::{ExecBranch, ExecutionSummary};
+use crate::parser::Program;
+use crate::smt::ctx;
+use crate::state::context::ExecutionEnv;
+use crate::state::evm::EvmState;
+
+use super::deploy::run_constructor;
+use super::{Execution, ExploreOptions};
+
+// The paths of a sequence of transactions against one contract: its constructor, then calls
+// to the code it deployed. Each call starts from every feasible end state of the transaction
+// before it, so storage and balances carry over from one transaction to the next.
+pub struct TransactionSequence {
+    // Halted paths of each transaction, the constructor's first. The conditions of a path
+    // include those of the transactions before it.
+    transactions: Vec<Vec<ExecBranch<'static>>>,
+}
+
+impl TransactionSequence {
+    // Runs `init_code` as the constructor of the account of `template`, then `calls`
+    // transactions with a symbolic caller, calldata and callvalue each. `template` is the
+    // state every transaction starts from, e.g. with a gas limit or memory model.
+    pub fn explore(
+        template: &EvmState,
+        init_code: Program,
+        env: &ExecutionEnv,
+        options: &ExploreOptions,
+        calls: usize,
+    ) -> Self {
+        let mut transactions = vec![run_constructor(template, init_code, env, options)];
+        for index in 1..=calls {
+            let env = env.clone().for_transaction(index);
+            let next = transactions
+                .last()
+                .unwrap()
+                .iter()
+                .filter_map(|(end, conditions)| {
+                    let start = next_transaction(template, end, conditions)?;
+                    Some(run_transaction(start, &env, conditions.clone(), options))
+                })
+                .flatten()
+                .collect();
+            transactions.push(next);
+        }
+        Self { transactions }
+    }
+
+    // Halted paths of transaction `index`; the constructor is transaction zero
+    pub fn paths(&self, index: usize) -> &[ExecBranch<'static>] {
+        self.transactions
+            .get(index)
+            .map(|paths| paths.as_slice())
+            .unwrap_or_default()
+    }
+
+    // Every halted path of every transaction, shorter sequences first, so that `falsify`
+    // reports a violation after as few transactions as it can
+    pub fn summary(&self) -> ExecutionSummary<'static> {
+        ExecutionSummary::with_branches(self.transactions.iter().flatten().cloned().collect())
+    }
+}
+
+// The start of the transaction after the one that ended in `end`. None if that one failed,
+// since a failed transaction changes nothing and the sequence without it is explored anyway,
+// or if its path can't be taken or left no code to call.
+fn next_transaction(
+    template: &EvmState,
+    end: &EvmState,
+    conditions: &[Bool<'static>],
+) -> Option<EvmState> {
+    if !end.result.as_ref()?.is_success() || !feasible(end, conditions) {
+        return None;
+    }
+    let mut start = end.next_transaction()?;
+    start.gas = template.gas.clone();
+    start.memory = template.memory.clone();
+    Some(start)
+}
+
+// Runs one transaction from `start` and returns its halted paths. `path` holds the conditions
+// of the transactions before it, which hold on each of its paths.
+pub(super) fn run_transaction(
+    mut start: EvmState,
+    env: &ExecutionEnv,
+    mut path: Vec<Bool<'static>>,
+    options: &ExploreOptions,
+) -> Vec<ExecBranch<'static>> {
+    let can_pay = start.begin_transaction(env);
+    if can_pay.as_bool() != Some(true) {
+        path.push(can_pay);
+    }
+    let pgm = start.pgm.clone();
+    let mut execution: Execution<'static> =
+        Execution::new(start, pgm).with_options(options.clone());
+    if !path.is_empty() {
+        let path = path.iter().collect::<Vec<_>>();
+        execution.states.path_condition = Some(Bool::and(ctx(), &path));
+    }
+    execution.run(env);
+    execution.states.leaf_branches()
+}
+
+// Whether the path that ended in `end` under `conditions` can be taken
+pub(super) fn feasible(end: &EvmState, conditions: &[Bool<'static>]) -> bool {
+    let solver = Solver::new(ctx());
+    conditions.iter().for_each(|c| solver.assert(c));
+    keccak::axioms(&end.hashes).iter().for_each(|axiom| solver.assert(axiom));
+    solver.check() != SatResult::Unsat
+}
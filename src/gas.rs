@@ -0,0 +1,638 @@
+use z3_ext::ast::{Ast, Bool, BV};
+
+use crate::exec::call::code_at;
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::record::MachineRecord;
+use crate::smt::{ctx, BitVec};
+use crate::state::context::ExecutionEnv;
+use crate::state::evm::EvmState;
+use crate::storage::{Address, StorageValue};
+use crate::traits::MachineState;
+
+// Gas available to a transaction when none is configured: the block gas limit on mainnet
+pub const DEFAULT_GAS_LIMIT: u64 = 30_000_000;
+
+// Costs from the yellow paper as amended by EIP-2929 (cold and warm accesses), EIP-3529
+// (reduced refunds) and EIP-3860 (initcode metering)
+pub const G_BASE: u64 = 2;
+pub const G_VERYLOW: u64 = 3;
+pub const G_LOW: u64 = 5;
+pub const G_MID: u64 = 8;
+pub const G_HIGH: u64 = 10;
+pub const G_JUMPDEST: u64 = 1;
+pub const G_BLOCKHASH: u64 = 20;
+pub const G_WARM_ACCESS: u64 = 100;
+pub const G_COLD_SLOAD: u64 = 2100;
+pub const G_COLD_ACCOUNT_ACCESS: u64 = 2600;
+pub const G_SSET: u64 = 20000;
+pub const G_SRESET: u64 = 2900;
+pub const R_SCLEAR: u64 = 4800;
+pub const G_CALL_VALUE: u64 = 9000;
+pub const G_NEW_ACCOUNT: u64 = 25000;
+// Given to the callee of a call that transfers value, on top of the gas the caller forwards
+pub const G_CALL_STIPEND: u64 = 2300;
+pub const G_CREATE: u64 = 32000;
+pub const G_INITCODE_WORD: u64 = 2;
+// Paid by a constructor per byte of the runtime code it returns
+pub const G_CODE_DEPOSIT: u64 = 200;
+pub const G_SELFDESTRUCT: u64 = 5000;
+pub const G_EXP: u64 = 10;
+pub const G_EXP_BYTE: u64 = 50;
+pub const G_MEMORY: u64 = 3;
+pub const G_COPY: u64 = 3;
+pub const G_KECCAK: u64 = 30;
+pub const G_KECCAK_WORD: u64 = 6;
+pub const G_LOG: u64 = 375;
+pub const G_LOG_TOPIC: u64 = 375;
+pub const G_LOG_DATA: u64 = 8;
+
+// Sizes are capped at this many bytes before being priced, so that the products below can't
+// wrap around. Anything this large runs out of gas under any realistic limit.
+const MAX_PRICED_BYTES: u64 = 1 << 32;
+
+// Gas accounting of one path through a frame. Costs that depend on symbolic operands make
+// `used` symbolic.
+#[derive(Debug, Clone)]
+pub struct GasMeter {
+    limit: u64,
+    used: BitVec<32>,
+    // The most `used` can be. While it leaves room for a charge, the charge can't exceed the
+    // limit and the solver isn't asked.
+    max_used: u64,
+    // Refund counter, only paid out at the end of the transaction
+    refund: BitVec<32>,
+    // Accounts accessed on this path, which stay warm for the rest of it
+    accessed: Vec<Address>,
+}
+
+// What `GasMeter::restore` needs to undo every charge made after it was taken. The accessed
+// accounts only grow, so their count is enough.
+#[derive(Debug, Clone)]
+pub struct GasCheckpoint {
+    used: BitVec<32>,
+    max_used: u64,
+    refund: BitVec<32>,
+    accessed: usize,
+}
+
+impl Default for GasMeter {
+    fn default() -> Self {
+        Self::with_limit(DEFAULT_GAS_LIMIT)
+    }
+}
+
+// Gas charged for executing one instruction
+#[derive(Debug, Clone)]
+pub struct Charge {
+    pub cost: BitVec<32>,
+    // The most `cost` can be, whatever its symbolic operands turn out to be
+    pub max: u64,
+    pub refund: BitVec<32>,
+    pub accessed: Vec<Address>,
+}
+
+impl GasMeter {
+    pub fn with_limit(limit: u64) -> Self {
+        Self {
+            limit,
+            used: word(0).into(),
+            max_used: 0,
+            refund: word(0).into(),
+            accessed: vec![],
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn used(&self) -> &BitVec<32> {
+        &self.used
+    }
+
+    pub fn max_used(&self) -> u64 {
+        self.max_used
+    }
+
+    pub fn refund(&self) -> &BitVec<32> {
+        &self.refund
+    }
+
+    pub fn remaining(&self) -> BitVec<32> {
+        word(self.limit).bvsub(self.used.as_ref()).simplify().into()
+    }
+
+    // Gas used once the refund is paid out. The refund is capped at a fifth of the gas used.
+    pub fn net_used(&self) -> BitVec<32> {
+        let used = self.used.as_ref();
+        let cap = used.bvudiv(&word(5));
+        let refund = self.refund.as_ref();
+        let paid = refund.bvule(&cap).ite(refund, &cap);
+        used.bvsub(&paid).simplify().into()
+    }
+
+    pub fn is_warm(&self, addr: &Address) -> bool {
+        self.accessed.contains(addr)
+    }
+
+    pub fn accessed(&self) -> &[Address] {
+        &self.accessed
+    }
+
+    pub fn charge(&mut self, charge: Charge) {
+        self.used = self.used.as_ref().bvadd(charge.cost.as_ref()).simplify().into();
+        self.max_used = self.max_used.saturating_add(charge.max);
+        self.refund = self.refund.as_ref().bvadd(charge.refund.as_ref()).simplify().into();
+        charge.accessed.into_iter().for_each(|addr| {
+            if !self.accessed.contains(&addr) {
+                self.accessed.push(addr);
+            }
+        });
+    }
+
+    pub fn checkpoint(&self) -> GasCheckpoint {
+        GasCheckpoint {
+            used: self.used.clone(),
+            max_used: self.max_used,
+            refund: self.refund.clone(),
+            accessed: self.accessed.len(),
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: GasCheckpoint) {
+        self.used = checkpoint.used;
+        self.max_used = checkpoint.max_used;
+        self.refund = checkpoint.refund;
+        self.accessed.truncate(checkpoint.accessed);
+    }
+
+    // Exceptional halts use up all of the gas of the frame
+    pub fn consume_all(&mut self) {
+        self.used = word(self.limit).into();
+        self.max_used = self.limit;
+    }
+
+    // Whether paying `charge` would take the gas used past the limit. Surely not if the most
+    // used so far and the most `charge` can cost fit in the limit, which spares paths with
+    // symbolic gas a solver check on every step.
+    pub fn exceeds_limit(&self, charge: &Charge) -> Bool<'static> {
+        if self.max_used.saturating_add(charge.max) <= self.limit {
+            return Bool::from_bool(ctx(), false);
+        }
+        let used = self.used.as_ref().bvadd(charge.cost.as_ref());
+        used.bvugt(&word(self.limit)).simplify()
+    }
+}
+
+// The gas charged for applying `rec` to `state`, which must be the state the record was made
+// from. Covers the instruction's static cost, memory expansion and its dynamic costs.
+pub fn charge(state: &EvmState, rec: &MachineRecord<32>) -> Charge {
+    let creates_account = match rec.call.as_ref() {
+        Some(call) => call.creates_account.clone(),
+        None => Bool::from_bool(ctx(), false),
+    };
+    match state.pgm.get(rec.pc.0) {
+        Some(inst) => price(state, &inst, &creates_account),
+        None => Charge::free(),
+    }
+}
+
+// Whether the instruction `state` is about to execute runs out of gas. It is priced from its
+// operands alone, so that e.g. a huge copy runs out of gas before its bytes are built. SSTORE
+// also fails when no more than the call stipend is left (EIP-2200). An instruction missing
+// operands is left to underflow.
+pub fn out_of_gas(state: &EvmState, env: &ExecutionEnv) -> Bool<'static> {
+    let inst = match state.pgm.get(state.pc()) {
+        Some(inst) if state.stack().size() >= inst.stack_io().0 => inst,
+        _ => return Bool::from_bool(ctx(), false),
+    };
+    let charge = price(state, &inst, &creates_account(state, env));
+    let exceeds = state.gas.exceeds_limit(&charge);
+    match inst {
+        // Surely more than the stipend is left while the most used so far leaves room for it
+        Instruction::SStore
+            if state.gas.max_used().saturating_add(G_CALL_STIPEND) >= state.gas.limit() =>
+        {
+            let within_stipend = state.gas.remaining().as_ref().bvule(&word(G_CALL_STIPEND));
+            Bool::or(ctx(), &[&exceeds, &within_stipend]).simplify()
+        }
+        _ => exceeds,
+    }
+}
+
+// Whether the CALL `state` is about to make sends value to an empty account, which brings the
+// account into existence (EIP-161). An account with code or a nonce isn't empty; any other is
+// empty while it holds no balance.
+pub fn creates_account(state: &EvmState, env: &ExecutionEnv) -> Bool<'static> {
+    let stack = state.stack();
+    let (target, value) = match (state.pgm.get(state.pc()), stack.peek_nth(1), stack.peek_nth(2)) {
+        (Some(Instruction::Call), Some(target), Some(value)) => (target, value),
+        _ => return Bool::from_bool(ctx(), false),
+    };
+    let target: Address = target.as_ref().extract(159, 0).simplify().into();
+    let has_code = code_at(state, env, &target).map_or(false, |code| !code.bytes.is_empty());
+    let has_nonce = state
+        .world
+        .account(&target)
+        .map_or(false, |account| account.nonce() > 0);
+    if has_code || has_nonce {
+        return Bool::from_bool(ctx(), false);
+    }
+    let zero = word(0);
+    let sends_value = value.as_ref()._eq(&zero).not();
+    let unfunded = state.world.balances.balance(&target).as_ref()._eq(&zero);
+    Bool::and(ctx(), &[&sends_value, &unfunded]).simplify()
+}
+
+// What a constructor pays to deposit `size` bytes of runtime code
+pub fn code_deposit(size: usize) -> Charge {
+    let cost = G_CODE_DEPOSIT * size as u64;
+    Charge::cost(word(cost), cost)
+}
+
+// The gas limit of the frame entered by the call or creation of `rec`, and the stipend it is
+// given on top of what its caller forwards. Callers keep a 64th of the gas left after paying
+// for the instruction (EIP-150). Symbolic amounts are bounded by the caller's limit.
+pub fn frame_gas(caller: &EvmState, rec: &MachineRecord<32>) -> (u64, BitVec<32>) {
+    let left = caller
+        .gas
+        .remaining()
+        .as_ref()
+        .bvsub(charge(caller, rec).cost.as_ref())
+        .simplify()
+        .as_u64()
+        .unwrap_or(caller.gas.limit);
+    let available = left - left / 64;
+    let (requested, value) = match (rec.call.as_ref(), rec.create.as_ref()) {
+        (Some(call), _) => (Some(&call.gas), call.value.as_ref()),
+        _ => (None, None),
+    };
+    let limit = match requested.and_then(|gas| gas.as_ref().simplify().as_u64()) {
+        Some(requested) => requested.min(available),
+        None => available,
+    };
+    let stipend = match value {
+        Some(value) => value
+            .as_ref()
+            ._eq(&word(0))
+            .ite(&word(0), &word(G_CALL_STIPEND))
+            .simplify(),
+        None => word(0),
+    };
+    let limit = limit + stipend.as_u64().unwrap_or(G_CALL_STIPEND);
+    (limit, stipend.into())
+}
+
+impl Charge {
+    pub fn free() -> Self {
+        Self {
+            cost: word(0).into(),
+            max: 0,
+            refund: word(0).into(),
+            accessed: vec![],
+        }
+    }
+
+    // A charge of `cost`, which is at most `max` if it is symbolic
+    fn cost(cost: BV<'static>, max: u64) -> Self {
+        let max = cost.simplify().as_u64().unwrap_or(max);
+        Self {
+            cost: cost.into(),
+            max,
+            ..Self::free()
+        }
+    }
+}
+
+// The gas charged for executing `inst` from `state`, worked out from its stack operands.
+// `creates_account` is whether a CALL brings the account it sends value to into existence.
+fn price(state: &EvmState, inst: &Instruction, creates_account: &Bool<'static>) -> Charge {
+    let mut charge = dynamic_charge(inst, state, creates_account);
+    let mut cost = charge.cost.as_ref().bvadd(&word(static_cost(inst)));
+    let mut max = charge.max.saturating_add(static_cost(inst));
+    let regions = memory_regions(inst, state);
+    if !regions.is_empty() {
+        let before = memory_cost(state.memory.msize().as_ref());
+        let after = memory_cost(&expanded_size(&state.memory, &regions));
+        let expansion = after.bvsub(&before).simplify();
+        let bound = expansion.as_u64().unwrap_or_else(|| max_expansion(&regions));
+        max = max.saturating_add(bound);
+        cost = cost.bvadd(&expansion);
+    }
+    let cost = cost.simplify();
+    charge.max = cost.as_u64().unwrap_or(max);
+    charge.cost = cost.into();
+    charge
+}
+
+pub fn static_cost(inst: &Instruction) -> u64 {
+    use Instruction::*;
+    match inst {
+        Stop | Return | Revert | Invalid => 0,
+        // Priced entirely by the accounts and slots they access
+        Balance | ExtCodeSize | ExtCodeCopy | ExtCodeHash | SLoad | SStore | Call | CallCode
+        | DelegateCall | StaticCall => 0,
+        JumpDest => G_JUMPDEST,
+        Address | Origin | Caller | CallValue | CallDataSize | CodeSize | GasPrice
+        | ReturnDataSize | Coinbase | Timestamp | Number | Difficulty | GasLimit | ChainId
+        | BaseFee | Pop | Pc | MSize | Gas => G_BASE,
+        Mul | Div | SDiv | Mod | SMod | SignExtend | SelfBalance => G_LOW,
+        AddMod | MulMod | Jump => G_MID,
+        JumpI | Exp => G_HIGH,
+        BlockHash => G_BLOCKHASH,
+        Sha3 => G_KECCAK,
+        Log0 | Log1 | Log2 | Log3 | Log4 => G_LOG,
+        Create | Create2 => G_CREATE,
+        SelfDestruct => G_SELFDESTRUCT,
+        // Arithmetic, comparisons, memory words, calldata, copies, PUSH, DUP and SWAP
+        _ => G_VERYLOW,
+    }
+}
+
+fn dynamic_charge(inst: &Instruction, state: &EvmState, creates_account: &Bool<'static>) -> Charge {
+    use Instruction::*;
+    let arg = |n: usize| state.stack().peek_nth(n).unwrap().as_ref().clone();
+    match inst {
+        Exp => Charge::cost(exponent_bytes(&arg(1)).bvmul(&word(G_EXP_BYTE)), 32 * G_EXP_BYTE),
+        Sha3 => Charge::cost(
+            words(&arg(1)).bvmul(&word(G_KECCAK_WORD)),
+            max_words(&arg(1)) * G_KECCAK_WORD,
+        ),
+        CallDataCopy | CodeCopy | ReturnDataCopy => {
+            Charge::cost(words(&arg(2)).bvmul(&word(G_COPY)), max_words(&arg(2)) * G_COPY)
+        }
+        ExtCodeCopy => {
+            let mut charge = account_access(state, &arg(0), G_WARM_ACCESS);
+            let copy = words(&arg(3)).bvmul(&word(G_COPY));
+            charge.cost = charge.cost.as_ref().bvadd(&copy).into();
+            charge.max += max_words(&arg(3)) * G_COPY;
+            charge
+        }
+        Log0 | Log1 | Log2 | Log3 | Log4 => {
+            let topics = match inst {
+                Log0 => 0,
+                Log1 => 1,
+                Log2 => 2,
+                Log3 => 3,
+                _ => 4,
+            };
+            let data = capped(&arg(1)).bvmul(&word(G_LOG_DATA));
+            let max = max_size(&arg(1)) * G_LOG_DATA + topics as u64 * G_LOG_TOPIC;
+            Charge::cost(data.bvadd(&word(topics as u64 * G_LOG_TOPIC)), max)
+        }
+        Create => Charge::cost(
+            words(&arg(2)).bvmul(&word(G_INITCODE_WORD)),
+            max_words(&arg(2)) * G_INITCODE_WORD,
+        ),
+        // CREATE2 also hashes the init code
+        Create2 => Charge::cost(
+            words(&arg(2)).bvmul(&word(G_INITCODE_WORD + G_KECCAK_WORD)),
+            max_words(&arg(2)) * (G_INITCODE_WORD + G_KECCAK_WORD),
+        ),
+        Balance | ExtCodeSize | ExtCodeHash => account_access(state, &arg(0), G_WARM_ACCESS),
+        // Unlike other accesses, a warm beneficiary costs nothing extra
+        SelfDestruct => account_access(state, &arg(0), 0),
+        Call | CallCode => {
+            let mut charge = account_access(state, &arg(1), G_WARM_ACCESS);
+            let transfer = arg(2)._eq(&word(0)).ite(&word(0), &word(G_CALL_VALUE));
+            let new_account = creates_account.ite(&word(G_NEW_ACCOUNT), &word(0));
+            charge.cost = charge.cost.as_ref().bvadd(&transfer).bvadd(&new_account).into();
+            charge.max += G_CALL_VALUE;
+            if creates_account.as_bool() != Some(false) {
+                charge.max += G_NEW_ACCOUNT;
+            }
+            charge
+        }
+        DelegateCall | StaticCall => account_access(state, &arg(1), G_WARM_ACCESS),
+        SLoad => {
+            let key: BitVec<32> = arg(0).into();
+            let cost = if state.world.is_warm(&state.address, &key) {
+                G_WARM_ACCESS
+            } else {
+                G_COLD_SLOAD
+            };
+            Charge::cost(word(cost), cost)
+        }
+        SStore => sstore_charge(state, &arg(0).into(), &arg(1).into()),
+        _ => Charge::free(),
+    }
+}
+
+// SSTORE as priced by EIP-2200 with the costs of EIP-2929 and the refunds of EIP-3529. The
+// original value is the one the slot held before any write on this path.
+fn sstore_charge(state: &EvmState, key: &BitVec<32>, new: &BitVec<32>) -> Charge {
+    let StorageValue::BV(current) = state.world.sload(&state.address, key);
+    let original = state.world.original(&state.address, key);
+    let (new, current, original) = (new.as_ref(), current.as_ref(), original.as_ref());
+    let zero = word(0);
+
+    let unchanged = new._eq(current);
+    let clean = current._eq(original);
+    let cost = unchanged.ite(
+        &word(G_WARM_ACCESS),
+        &clean.ite(
+            &original._eq(&zero).ite(&word(G_SSET), &word(G_SRESET)),
+            &word(G_WARM_ACCESS),
+        ),
+    );
+    let cold = if state.world.is_warm(&state.address, key) {
+        0
+    } else {
+        G_COLD_SLOAD
+    };
+
+    let original_set = original._eq(&zero).not();
+    // Clearing a slot that was set before the transaction
+    let cleared = Bool::and(ctx(), &[&original_set, &new._eq(&zero)]).ite(&word(R_SCLEAR), &zero);
+    // A dirty slot takes back the refund for clearing it, or for restoring it
+    let uncleared = Bool::and(ctx(), &[&original_set, &current._eq(&zero)])
+        .ite(&word(R_SCLEAR).bvneg(), &zero);
+    let restored = new._eq(original).ite(
+        &original._eq(&zero).ite(
+            &word(G_SSET - G_WARM_ACCESS),
+            &word(G_SRESET - G_WARM_ACCESS),
+        ),
+        &zero,
+    );
+    let dirty_refund = uncleared.bvadd(&cleared).bvadd(&restored);
+    let refund = unchanged.ite(&zero, &clean.ite(&cleared, &dirty_refund));
+
+    let cost = cost.bvadd(&word(cold)).simplify();
+    Charge {
+        max: cost.as_u64().unwrap_or(G_SSET + cold),
+        cost: cost.into(),
+        refund: refund.simplify().into(),
+        accessed: vec![],
+    }
+}
+
+// Cold or warm access to the account at the low 20 bytes of `addr`. The executing account
+// is always warm; a symbolic address is warm only if it was accessed under the same term.
+fn account_access(state: &EvmState, addr: &BV<'static>, warm_cost: u64) -> Charge {
+    let addr: Address = addr.extract(159, 0).simplify().into();
+    let cost = if addr == state.address || state.gas.is_warm(&addr) {
+        warm_cost
+    } else {
+        G_COLD_ACCOUNT_ACCESS
+    };
+    Charge {
+        cost: word(cost).into(),
+        max: cost,
+        refund: word(0).into(),
+        accessed: vec![addr],
+    }
+}
+
+// The memory `inst` accesses, as (offset, size) pairs taken from its stack operands
+fn memory_regions(inst: &Instruction, state: &EvmState) -> Vec<(BV<'static>, BV<'static>)> {
+    use Instruction::*;
+    let arg = |n: usize| state.stack().peek_nth(n).unwrap().as_ref().clone();
+    let operands: &[(usize, usize)] = match inst {
+        MLoad | MStore => return vec![(arg(0), word(32))],
+        MStore8 => return vec![(arg(0), word(1))],
+        CallDataCopy | CodeCopy | ReturnDataCopy => &[(0, 2)],
+        ExtCodeCopy => &[(1, 3)],
+        Sha3 | Return | Revert | Log0 | Log1 | Log2 | Log3 | Log4 => &[(0, 1)],
+        Create | Create2 => &[(1, 2)],
+        Call | CallCode => &[(3, 4), (5, 6)],
+        DelegateCall | StaticCall => &[(2, 3), (4, 5)],
+        _ => &[],
+    };
+    operands
+        .iter()
+        .map(|(offset, size)| (arg(*offset), arg(*size)))
+        .collect()
+}
+
+// Size of `memory` once the `regions` are accessed, so that memory is only expanded once it is
+// known to be paid for. Offsets are capped like sizes, which prices any access past the cap
+// out of reach. Accessing no bytes doesn't expand memory, whatever the offset.
+fn expanded_size(memory: &Memory, regions: &[(BV<'static>, BV<'static>)]) -> BV<'static> {
+    let max = |a: &BV<'static>, b: BV<'static>| a.bvuge(&b).ite(a, &b);
+    regions.iter().fold(memory.msize().as_ref().clone(), |size, (offset, len)| {
+        let end = capped(offset).bvadd(&capped(len));
+        let end = end.bvadd(&word(31)).bvand(&word(31).bvnot());
+        len._eq(&word(0)).ite(&size, &max(&size, end)).simplify()
+    })
+}
+
+// The most expanding memory to cover `regions` can cost: that of a memory ending where the
+// furthest of them may end
+fn max_expansion(regions: &[(BV<'static>, BV<'static>)]) -> u64 {
+    let end = regions
+        .iter()
+        .map(|(offset, len)| max_size(offset) + max_size(len))
+        .max()
+        .unwrap_or(0);
+    memory_cost(&word(end)).simplify().as_u64().unwrap_or(u64::MAX)
+}
+
+// Total cost of a memory of `size` bytes: 3 per word plus the square of the words over 512
+fn memory_cost(size: &BV<'static>) -> BV<'static> {
+    let words = words(size);
+    let linear = words.bvmul(&word(G_MEMORY));
+    let quadratic = words.bvmul(&words).bvudiv(&word(512));
+    linear.bvadd(&quadratic)
+}
+
+// Number of words needed to hold `size` bytes
+fn words(size: &BV<'static>) -> BV<'static> {
+    capped(size).bvadd(&word(31)).bvudiv(&word(32))
+}
+
+// The most `words(size)` can be
+fn max_words(size: &BV<'static>) -> u64 {
+    max_size(size).div_ceil(32)
+}
+
+// The most `capped(size)` can be
+fn max_size(size: &BV<'static>) -> u64 {
+    match size.simplify().as_u64() {
+        Some(size) => size.min(MAX_PRICED_BYTES),
+        None => MAX_PRICED_BYTES,
+    }
+}
+
+fn capped(size: &BV<'static>) -> BV<'static> {
+    let max = word(MAX_PRICED_BYTES);
+    size.bvugt(&max).ite(&max, size)
+}
+
+// Number of bytes in the exponent of EXP, not counting leading zero bytes
+fn exponent_bytes(exponent: &BV<'static>) -> BV<'static> {
+    (0..32u32).fold(word(0), |bytes, i| {
+        let above = exponent.bvlshr(&word(8 * i as u64))._eq(&word(0)).not();
+        bytes.bvadd(&above.ite(&word(1), &word(0)))
+    })
+}
+
+fn word(val: u64) -> BV<'static> {
+    BV::from_u64(ctx(), val, 256)
+}
+
+#[test]
+fn test_sstore_charge() {
+    use crate::bvi;
+    use crate::record::{StorageChange, StorageOp};
+    use crate::traits::MachineComponent;
+    let mut state = EvmState::default();
+    state.world.create_account(&state.address, None);
+    let key: BitVec<32> = bvi(0);
+    let set = sstore_charge(&state, &key, &bvi(1));
+    assert_eq!(Some(G_SSET + G_COLD_SLOAD), set.cost.as_ref().as_u64());
+    assert_eq!(Some(0), set.refund.as_ref().as_u64());
+
+    state.world.apply_change(StorageChange {
+        log: vec![StorageOp::Write {
+            addr: state.address.clone(),
+            idx: key.clone(),
+            val: bvi(1),
+        }],
+    });
+    // Resetting a dirty slot to its original value refunds most of what setting it cost
+    let reset = sstore_charge(&state, &key, &bvi(0));
+    assert_eq!(Some(G_WARM_ACCESS), reset.cost.as_ref().as_u64());
+    assert_eq!(Some(G_SSET - G_WARM_ACCESS), reset.refund.as_ref().as_u64());
+}
+
+#[test]
+fn test_memory_and_exponent_costs() {
+    assert_eq!(Some(0), memory_cost(&word(0)).simplify().as_u64());
+    assert_eq!(Some(3), memory_cost(&word(32)).simplify().as_u64());
+    // 1024 words: 3 * 1024 + 1024^2 / 512
+    assert_eq!(Some(5120), memory_cost(&word(32 * 1024)).simplify().as_u64());
+    assert_eq!(Some(0), exponent_bytes(&word(0)).simplify().as_u64());
+    assert_eq!(Some(2), exponent_bytes(&word(0x100)).simplify().as_u64());
+}
+
+#[test]
+fn test_exceeds_limit_within_bound() {
+    let mut meter = GasMeter::with_limit(100_000);
+    let cold = BV::new_const(ctx(), "cold", 256)._eq(&word(0));
+    let access = cold.ite(&word(G_COLD_SLOAD), &word(G_WARM_ACCESS));
+    meter.charge(Charge::cost(access, G_COLD_SLOAD));
+    assert_eq!(None, meter.used().as_ref().as_u64());
+    assert_eq!(G_COLD_SLOAD, meter.max_used());
+    // Charges that fit under the bound are decided without a symbolic condition
+    let small = meter.exceeds_limit(&Charge::cost(word(1000), 0));
+    assert_eq!(Some(false), small.as_bool());
+    // Past it, whether the limit is exceeded depends on the earlier access
+    let large = meter.exceeds_limit(&Charge::cost(word(98_000), 0));
+    assert_eq!(None, large.as_bool());
+}
+
+#[test]
+fn test_expanded_size() {
+    let memory = Memory::default();
+    let write_byte = [(word(40), word(1))];
+    assert_eq!(Some(64), expanded_size(&memory, &write_byte).as_u64());
+    // Accessing no bytes leaves memory as it is
+    let empty = [(word(1 << 20), word(0))];
+    assert_eq!(Some(0), expanded_size(&memory, &empty).as_u64());
+    // A write far past the cap is priced without expanding memory
+    let far = [(word(1 << 40), word(32))];
+    let size = expanded_size(&memory, &far);
+    assert_eq!(Some(MAX_PRICED_BYTES + 32), size.as_u64());
+    assert_eq!(0, memory.m_size());
+}
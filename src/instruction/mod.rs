@@ -2,34 +2,54 @@ use std::collections::HashMap;
 use std::ops::{BitAnd, BitOr, BitXor};
 
 use ruint::aliases::U256;
-use z3_ext::ast::{Ast, Bool, BV};
-
-use crate::conversion::bitvec_array_to_bv;
-use crate::record::{push, MemChange, MemOp, StorageChange, StorageOp};
-use crate::state::context::ExecutionEnv;
+use z3_ext::ast::{Array, Ast, Bool, BV};
+use z3_ext::Sort;
+
+use crate::conversion::{concrete_byte_vec, concrete_bytes};
+use crate::exec::call::{code_at, MAX_CALL_DEPTH};
+use crate::gas::{self, G_BASE};
+use crate::keccak::keccak;
+use crate::record::{
+    push, CallChange, CallKind, CreateChange, MemChange, MemOp, ReturnData, StorageChange,
+    StorageOp,
+};
+use crate::state::context::{ExecutionEnv, Log, LogTopic};
 use crate::state::env::*;
 use crate::state::evm::EvmState;
 use crate::state::tree::StateTree;
-use crate::storage::StorageValue;
+use crate::storage::{create2_address, create_address, Address, StorageValue};
 use crate::traits::*;
 use crate::{
     bvi,
     machine::Evm,
-    memory::Memory,
+    memory::{Memory, MemoryModel, SYMBOLIC_COPY_BOUND},
     random_bv_arg,
     record::{Index, MachineRecord, StackChange, StackOp},
-    stack::Stack,
+    stack::{Stack, MAX_STACK_DEPTH},
 };
 
 use justerror::Error;
+use uuid::Uuid;
 use super::smt::*;
 
 
 #[Error]
 pub enum InstructionError {
-    StackEmpty{
+    StackUnderflow {
         pc: usize,
-    }
+        required: usize,
+        size: usize,
+    },
+    StackOverflow {
+        pc: usize,
+    },
+    StaticStateChange {
+        pc: usize,
+    },
+    // The instruction's operands can't be modelled; not an EVM error
+    Unsupported {
+        pc: usize,
+    },
 }
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instruction {
@@ -191,6 +211,7 @@ fn exec_dup_nth(mach: &EvmState, n: usize) -> MachineRecord<32> {
         halt: false,
         storage: None,
         constraints: None,
+        ..Default::default()
     }
 }
 
@@ -207,6 +228,368 @@ fn exec_swap_nth(mach: &EvmState, n: usize) -> MachineRecord<32> {
         halt: false,
         storage: None,
         constraints: None,
+        ..Default::default()
+    }
+}
+
+// Pushes a value read from the block or transaction environment
+fn exec_push_env(mach: &EvmState, val: BitVec<32>) -> MachineRecord<32> {
+    MachineRecord {
+        stack: Some(StackChange::with_ops(vec![push(val)])),
+        pc: (mach.pc(), mach.pc() + 1),
+        mem: None,
+        halt: false,
+        storage: None,
+        constraints: None,
+        ..Default::default()
+    }
+}
+
+// The address in the low 20 bytes of a stack word
+fn stack_address(word: &BitVec<32>) -> Address {
+    word.as_ref().extract(159, 0).simplify().into()
+}
+
+// Memory writes copying `size` bytes to `dest`, the i-th of which is `byte_at(i)`. A symbolic
+// size is unrolled up to SYMBOLIC_COPY_BOUND bytes, each written only if it is within `size`.
+fn copy_to_memory(
+    dest: &BitVec<32>,
+    size: &BitVec<32>,
+    byte_at: impl Fn(usize) -> BitVec<1>,
+) -> Vec<MemOp> {
+    let idx_at = |i: usize| -> Index {
+        let offset_add: BitVec<32> = bvi(i as i32);
+        dest.as_ref().bvadd(offset_add.as_ref()).simplify().into()
+    };
+    match size.as_ref().simplify().as_u64() {
+        Some(size) => (0..size as usize)
+            .map(|i| MemOp::WriteByte {
+                idx: idx_at(i),
+                val: byte_at(i),
+            })
+            .collect(),
+        None => (0..SYMBOLIC_COPY_BOUND)
+            .map(|i| {
+                let i_bv: BitVec<32> = bvi(i as i32);
+                MemOp::WriteByteIf {
+                    idx: idx_at(i),
+                    val: byte_at(i),
+                    cond: i_bv.as_ref().bvult(size.as_ref()),
+                }
+            })
+            .collect(),
+    }
+}
+
+// `base` ** `power`, wrapping around. None if the power is symbolic and the base is not a
+// concrete power of two.
+fn exp(base: &BitVec<32>, power: &BitVec<32>) -> Option<BitVec<32>> {
+    let one: BitVec<32> = bvi(1);
+    let exp = match concrete_bytes(power.as_ref()) {
+        // Square and multiply, over the bits of the power from the least significant
+        Some(power) => {
+            let bits = power
+                .iter()
+                .rev()
+                .flat_map(|byte| (0..8).map(move |i| (byte >> i) & 1 == 1))
+                .collect::<Vec<_>>();
+            let len = bits.iter().rposition(|bit| *bit).map_or(0, |last| last + 1);
+            let mut exp = one.as_ref().clone();
+            let mut square = base.as_ref().clone();
+            for (i, bit) in bits[..len].iter().enumerate() {
+                if *bit {
+                    exp = exp.bvmul(&square);
+                }
+                if i + 1 < len {
+                    square = square.bvmul(&square);
+                }
+            }
+            exp
+        }
+        // Powers of 2 ** k are shifts by k * power, which only stay within the word while the
+        // power is below 256
+        None => {
+            let k = log2(base)?;
+            if k == 0 {
+                return Some(one);
+            }
+            let k: BitVec<32> = bvi(k as i32);
+            let shifted = one.as_ref().bvshl(&power.as_ref().bvmul(k.as_ref()));
+            power
+                .as_ref()
+                .bvult(bvi::<32>(256).as_ref())
+                .ite(&shifted, bvi::<32>(0).as_ref())
+        }
+    };
+    Some(exp.simplify().into())
+}
+
+// The base 2 logarithm of `value` if it is a concrete power of two
+fn log2(value: &BitVec<32>) -> Option<u32> {
+    value
+        .as_ref()
+        .simplify()
+        .as_u64()
+        .filter(|value| value.is_power_of_two())
+        .map(|value| value.trailing_zeros())
+}
+
+// `bytes` as an array indexed by offset, holding zero past their end
+fn byte_array(bytes: &[BitVec<1>]) -> Array<'static> {
+    let ctx = ctx();
+    let zero: BitVec<1> = BitVec::default();
+    let empty = Array::const_array(ctx, &Sort::bitvector(ctx, 256), zero.as_ref());
+    bytes.iter().enumerate().fold(empty, |array, (i, byte)| {
+        let i: BitVec<32> = bvi(i as i32);
+        array.store(i.as_ref(), byte.as_ref())
+    })
+}
+
+// Memory expansion caused by accessing `size` bytes at `offset`. Zero-sized accesses do not
+// expand memory, and symbolic sizes are not tracked.
+fn mem_access(offset: &BitVec<32>, size: &BitVec<32>) -> Vec<MemOp> {
+    match size.as_ref().simplify().as_u64() {
+        Some(size) if size > 0 => vec![MemOp::Read {
+            idx: offset.clone(),
+            size: size as usize,
+        }],
+        _ => vec![],
+    }
+}
+
+// The callee's outcome is modelled by a fresh success flag and fresh return data of a fresh
+// length, of which at most the requested output size is copied to memory. The record's
+// constraint is the success flag, so the executor forks into a success branch (right) and a
+// failure branch (left). A call sending more value than the caller holds always fails.
+fn exec_call(mach: &EvmState, env: &ExecutionEnv, kind: CallKind) -> MachineRecord<32> {
+    let stack = mach.stack();
+    let (gas, target, value, args_offset, args_size, ret_offset, ret_size) = match kind {
+        CallKind::Call | CallKind::CallCode => {
+            let [gas, target, value, args_offset, args_size, ret_offset, ret_size] =
+                stack.peek_top().unwrap();
+            (gas, target, Some(value.clone()), args_offset, args_size, ret_offset, ret_size)
+        }
+        CallKind::DelegateCall | CallKind::StaticCall => {
+            let [gas, target, args_offset, args_size, ret_offset, ret_size] =
+                stack.peek_top().unwrap();
+            (gas, target, None, args_offset, args_size, ret_offset, ret_size)
+        }
+    };
+    let pop_qty = if value.is_some() { 7 } else { 6 };
+
+    let mut args_offset = args_offset.clone();
+    let mut args_size = args_size.clone();
+    let mut ret_offset = ret_offset.clone();
+    let mut ret_size = ret_size.clone();
+    args_offset.simplify();
+    args_size.simplify();
+    ret_offset.simplify();
+    ret_size.simplify();
+
+    let calldata = mach.mem().read_bytes(args_offset.clone(), args_size.clone());
+
+    let id = Uuid::new_v4();
+    let success = affordable(
+        mach,
+        value.as_ref(),
+        Bool::new_const(ctx(), format!("call_success_{}", id).as_str()),
+    );
+    let return_data = ReturnData::new_const(format!("call_{}_returndata", id));
+
+    let mut mem_ops = mem_access(&args_offset, &args_size);
+    mem_ops.extend(mem_access(&ret_offset, &ret_size));
+    mem_ops.extend(copy_return_data(&ret_offset, &ret_size, &return_data));
+
+    let flag: BitVec<32> = success
+        .ite(bvi::<32>(1).as_ref(), bvi::<32>(0).as_ref())
+        .into();
+    let mut ops = (0..pop_qty).map(|_| pop()).collect::<Vec<_>>();
+    ops.push(push(flag));
+
+    let call = CallChange {
+        kind,
+        gas: gas.clone(),
+        target: stack_address(target),
+        value,
+        calldata,
+        ret_offset,
+        ret_size,
+        return_data,
+        success: success.clone(),
+        creates_account: gas::creates_account(mach, env),
+    };
+
+    MachineRecord {
+        stack: Some(StackChange::with_ops(ops)),
+        mem: Some(MemChange { ops_log: mem_ops }),
+        pc: (mach.pc(), mach.pc() + 1),
+        constraints: Some(success),
+        halt: false,
+        call: Some(call),
+        storage: None,
+        ..Default::default()
+    }
+}
+
+// Memory writes copying the output of a call to `ret_offset`: the first `ret_size` bytes of
+// it, or all of it if it is shorter
+pub(crate) fn copy_return_data(
+    ret_offset: &BitVec<32>,
+    ret_size: &BitVec<32>,
+    return_data: &ReturnData,
+) -> Vec<MemOp> {
+    let idx_at = |i: usize| -> Index {
+        let offset_add: BitVec<32> = bvi(i as i32);
+        ret_offset.as_ref().bvadd(offset_add.as_ref()).simplify().into()
+    };
+    let within = |i: usize, size: &BitVec<32>| {
+        let i: BitVec<32> = bvi(i as i32);
+        i.as_ref().bvult(size.as_ref()).simplify()
+    };
+    match (ret_size.as_ref().simplify().as_u64(), return_data) {
+        (Some(ret_size), ReturnData::Bytes(bytes)) => bytes
+            .iter()
+            .take(ret_size as usize)
+            .enumerate()
+            .map(|(i, byte)| MemOp::WriteByte {
+                idx: idx_at(i),
+                val: byte.clone(),
+            })
+            .collect(),
+        (None, ReturnData::Bytes(bytes)) => bytes
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| MemOp::WriteByteIf {
+                idx: idx_at(i),
+                val: byte.clone(),
+                cond: within(i, ret_size),
+            })
+            .collect(),
+        // Only the length of the output is unknown, so the copy is unrolled up to ret_size
+        (Some(ret_size), ReturnData::Symbolic { size, .. }) => (0..ret_size as usize)
+            .map(|i| MemOp::WriteByteIf {
+                idx: idx_at(i),
+                val: return_data.byte(&bvi(i as i32)),
+                cond: within(i, size),
+            })
+            .collect(),
+        (None, ReturnData::Symbolic { size, .. }) => {
+            let copied: BitVec<32> = ret_size
+                .as_ref()
+                .bvult(size.as_ref())
+                .ite(ret_size.as_ref(), size.as_ref())
+                .into();
+            copy_to_memory(ret_offset, &copied, |i| return_data.byte(&bvi(i as i32)))
+        }
+    }
+}
+
+// `success`, further requiring that the executing account holds `value`
+fn affordable(
+    mach: &EvmState,
+    value: Option<&BitVec<32>>,
+    success: Bool<'static>,
+) -> Bool<'static> {
+    match value {
+        Some(value) => {
+            let can_afford = mach.world.balances.can_afford(&mach.address, value);
+            Bool::and(ctx(), &[&success, &can_afford]).simplify()
+        }
+        None => success,
+    }
+}
+
+fn exec_log(mach: &EvmState, topic_qty: usize) -> MachineRecord<32> {
+    let stack = mach.stack();
+    let mut offset = stack.peek().unwrap().clone();
+    let mut size = stack.peek_nth(1).unwrap().clone();
+    offset.simplify();
+    size.simplify();
+    let topics = (0..topic_qty)
+        .map(|i| stack.peek_nth(i + 2).unwrap().clone())
+        .collect::<Vec<_>>();
+    let log = Log {
+        data: mach.mem().read_bytes(offset.clone(), size.clone()),
+        topics: LogTopic::from_slice(&topics),
+    };
+    let ops = (0..topic_qty + 2).map(|_| pop()).collect::<Vec<_>>();
+
+    MachineRecord {
+        stack: Some(StackChange::with_ops(ops)),
+        mem: Some(MemChange {
+            ops_log: mem_access(&offset, &size),
+        }),
+        pc: (mach.pc(), mach.pc() + 1),
+        constraints: None,
+        halt: false,
+        log: Some(log),
+        storage: None,
+        ..Default::default()
+    }
+}
+
+fn exec_create(mach: &EvmState, salted: bool) -> MachineRecord<32> {
+    let stack = mach.stack();
+    let (value, offset, size, salt) = if salted {
+        let [value, offset, size, salt] = stack.peek_top().unwrap();
+        (value, offset, size, Some(salt.clone()))
+    } else {
+        let [value, offset, size] = stack.peek_top().unwrap();
+        (value, offset, size, None)
+    };
+    let pop_qty = if salted { 4 } else { 3 };
+
+    let mut offset = offset.clone();
+    let mut size = size.clone();
+    offset.simplify();
+    size.simplify();
+    let init_code = mach.mem().read_bytes(offset.clone(), size.clone());
+
+    // The new address can only be derived when everything it is hashed from is concrete
+    let sender = concrete_bytes(mach.address.as_ref());
+    let address = match (&salt, sender) {
+        (None, Some(sender)) => Some(create_address(&sender, mach.world.nonce(&mach.address))),
+        (Some(salt), Some(sender)) => concrete_bytes(salt.as_ref())
+            .zip(concrete_byte_vec(&init_code))
+            .map(|(salt, code)| create2_address(&sender, &salt, &code)),
+        _ => None,
+    };
+    let id = Uuid::new_v4();
+    let address =
+        address.unwrap_or_else(|| Address::new_const(format!("create_address_{}", id)));
+
+    let success = affordable(
+        mach,
+        Some(value),
+        Bool::new_const(ctx(), format!("create_success_{}", id).as_str()),
+    );
+    let pushed: BitVec<32> = success
+        .ite(&address.as_ref().zero_ext(96), bvi::<32>(0).as_ref())
+        .into();
+    let mut ops = (0..pop_qty).map(|_| pop()).collect::<Vec<_>>();
+    ops.push(push(pushed));
+
+    let create = CreateChange {
+        value: value.clone(),
+        init_code,
+        salt,
+        address,
+        deployed: None,
+        return_data: ReturnData::default(),
+        success: success.clone(),
+    };
+
+    MachineRecord {
+        stack: Some(StackChange::with_ops(ops)),
+        mem: Some(MemChange {
+            ops_log: mem_access(&offset, &size),
+        }),
+        pc: (mach.pc(), mach.pc() + 1),
+        constraints: Some(success),
+        halt: false,
+        create: Some(create),
+        storage: None,
+        ..Default::default()
     }
 }
 
@@ -249,10 +632,191 @@ impl Instruction {
         };
         inst_additional_size + 1
     }
+
+    // Number of stack items the instruction pops and pushes
+    pub fn stack_io(&self) -> (usize, usize) {
+        use Instruction::*;
+        match self {
+            Stop | JumpDest | Invalid => (0, 0),
+            Address | Origin | Caller | CallValue | CallDataSize | CodeSize | GasPrice
+            | ReturnDataSize | Coinbase | Timestamp | Number | Difficulty | GasLimit | ChainId
+            | SelfBalance | BaseFee | Pc | MSize | Gas => (0, 1),
+            Push1(_) | Push2(_) | Push3(_) | Push4(_) | Push5(_) | Push6(_) | Push7(_)
+            | Push8(_) | Push9(_) | Push10(_) | Push11(_) | Push12(_) | Push13(_) | Push14(_)
+            | Push15(_) | Push16(_) | Push17(_) | Push18(_) | Push19(_) | Push20(_)
+            | Push21(_) | Push22(_) | Push23(_) | Push24(_) | Push25(_) | Push26(_)
+            | Push27(_) | Push28(_) | Push29(_) | Push30(_) | Push31(_) | Push32(_)
+            | Push(_) => (0, 1),
+            Pop | Jump | SelfDestruct => (1, 0),
+            Not | IsZero | Balance | CallDataLoad | ExtCodeSize | ExtCodeHash | BlockHash
+            | MLoad | SLoad => (1, 1),
+            MStore | MStore8 | SStore | JumpI | Return | Revert | Log0 => (2, 0),
+            Add | Mul | Sub | Div | SDiv | Mod | SMod | Exp | SignExtend | Lt | Gt | Slt
+            | Sgt | Eq | And | Or | Xor | Byte | Shl | Shr | Sha3 => (2, 1),
+            CallDataCopy | CodeCopy | ReturnDataCopy | Log1 => (3, 0),
+            AddMod | MulMod | Create => (3, 1),
+            ExtCodeCopy | Log2 => (4, 0),
+            Create2 => (4, 1),
+            Log3 => (5, 0),
+            Log4 => (6, 0),
+            DelegateCall | StaticCall => (6, 1),
+            Call | CallCode => (7, 1),
+            Dup1 => (1, 2),
+            Dup2 => (2, 3),
+            Dup3 => (3, 4),
+            Dup4 => (4, 5),
+            Dup5 => (5, 6),
+            Dup6 => (6, 7),
+            Dup7 => (7, 8),
+            Dup8 => (8, 9),
+            Dup9 => (9, 10),
+            Dup10 => (10, 11),
+            Dup11 => (11, 12),
+            Dup12 => (12, 13),
+            Dup13 => (13, 14),
+            Dup14 => (14, 15),
+            Dup15 => (15, 16),
+            Dup16 => (16, 17),
+            Swap1 => (2, 2),
+            Swap2 => (3, 3),
+            Swap3 => (4, 4),
+            Swap4 => (5, 5),
+            Swap5 => (6, 6),
+            Swap6 => (7, 7),
+            Swap7 => (8, 8),
+            Swap8 => (9, 9),
+            Swap9 => (10, 10),
+            Swap10 => (11, 11),
+            Swap11 => (12, 12),
+            Swap12 => (13, 13),
+            Swap13 => (14, 14),
+            Swap14 => (15, 15),
+            Swap15 => (16, 16),
+            Swap16 => (17, 17),
+        }
+    }
 }
+
 impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
     type Error = InstructionError;
-    fn exec(&self, mach: &EvmState, env: &ExecutionEnv) -> MachineRecord<32> {
+    // Fails without producing a record when the instruction would halt exceptionally
+    fn exec(&self, mach: &EvmState, env: &ExecutionEnv) -> Result<MachineRecord<32>, InstructionError> {
+        let pc = mach.pc();
+        let (pop_qty, push_qty) = self.stack_io();
+        let size = mach.stack().size();
+        if size < pop_qty {
+            return Err(InstructionError::StackUnderflow { pc, required: pop_qty, size });
+        }
+        if size - pop_qty + push_qty > MAX_STACK_DEPTH {
+            return Err(InstructionError::StackOverflow { pc });
+        }
+        if mach.is_static && self.changes_state(mach) {
+            return Err(InstructionError::StaticStateChange { pc });
+        }
+        if self.unsupported(mach) {
+            return Err(InstructionError::Unsupported { pc });
+        }
+        Ok(self.record(mach, env))
+    }
+}
+
+impl Instruction {
+    // Whether the instruction surely changes the world, which is not allowed in a static frame
+    fn changes_state(&self, mach: &EvmState) -> bool {
+        use Instruction::*;
+        match self {
+            SStore | Log0 | Log1 | Log2 | Log3 | Log4 | Create | Create2 | SelfDestruct => true,
+            // Only CALL moves value out of the executing account
+            Call => {
+                let value = mach.stack().peek_nth(2).unwrap().as_ref().simplify();
+                matches!(value.as_u64(), Some(value) if value != 0)
+            }
+            _ => false,
+        }
+    }
+
+    // The condition under which the instruction copies a symbolic number of bytes that may be
+    // more than SYMBOLIC_COPY_BOUND, past which copies are not unrolled
+    pub fn beyond_copy_bound(&self, mach: &EvmState, env: &ExecutionEnv) -> Bool<'static> {
+        use Instruction::*;
+        let stack = mach.stack();
+        // The output of a callee that is executed is copied in full, and a call past the depth
+        // limit fails without output
+        let unknown_callee = || {
+            let target = stack_address(stack.peek_nth(1).unwrap());
+            mach.depth < MAX_CALL_DEPTH && code_at(mach, env, &target).is_none()
+        };
+        let size = match self {
+            CallDataCopy | CodeCopy | ReturnDataCopy => stack.peek_nth(2),
+            ExtCodeCopy => stack.peek_nth(3),
+            Call | CallCode if unknown_callee() => stack.peek_nth(6),
+            DelegateCall | StaticCall if unknown_callee() => stack.peek_nth(5),
+            _ => None,
+        };
+        match size.map(|size| size.as_ref().simplify()) {
+            Some(size) if size.as_u64().is_none() => {
+                let bound: BitVec<32> = bvi(SYMBOLIC_COPY_BOUND as i32);
+                size.bvugt(bound.as_ref())
+            }
+            _ => Bool::from_bool(ctx(), false),
+        }
+    }
+
+    // Whether the operands of the instruction are ones its record can't be built from
+    fn unsupported(&self, mach: &EvmState) -> bool {
+        let stack = mach.stack();
+        let symbolic = |n: usize| stack.peek_nth(n).unwrap().as_ref().simplify().as_u64().is_none();
+        // The concrete memory model indexes a vector, so offsets into memory must be concrete
+        let offsets: &[usize] = match self {
+            Instruction::MLoad
+            | Instruction::MStore
+            | Instruction::MStore8
+            | Instruction::CallDataCopy
+            | Instruction::CodeCopy
+            | Instruction::ReturnDataCopy
+            | Instruction::Sha3
+            | Instruction::Return
+            | Instruction::Revert
+            | Instruction::Log0
+            | Instruction::Log1
+            | Instruction::Log2
+            | Instruction::Log3
+            | Instruction::Log4 => &[0],
+            Instruction::ExtCodeCopy | Instruction::Create | Instruction::Create2 => &[1],
+            Instruction::Call | Instruction::CallCode => &[3, 5],
+            Instruction::DelegateCall | Instruction::StaticCall => &[2, 4],
+            _ => &[],
+        };
+        if mach.memory.model() == MemoryModel::Concrete && offsets.iter().any(|n| symbolic(*n)) {
+            return true;
+        }
+        match self {
+            // The calldata passed to the callee is read from memory. A static frame may only
+            // make a CALL that is known not to send value.
+            Instruction::Call => symbolic(4) || (mach.is_static && symbolic(2)),
+            Instruction::CallCode => symbolic(4),
+            Instruction::DelegateCall | Instruction::StaticCall => symbolic(3),
+            Instruction::Exp => {
+                let [a, power] = stack.peek_top().unwrap();
+                concrete_bytes(power.as_ref()).is_none() && log2(a).is_none()
+            }
+            // Not modelled yet
+            Instruction::SelfDestruct => true,
+            // The bytes read from memory must be a concrete number, e.g. to be hashed or to
+            // become the output of the frame
+            Instruction::Sha3 | Instruction::Return | Instruction::Revert => symbolic(1),
+            Instruction::Log0
+            | Instruction::Log1
+            | Instruction::Log2
+            | Instruction::Log3
+            | Instruction::Log4 => symbolic(1),
+            Instruction::Create | Instruction::Create2 => symbolic(2),
+            _ => false,
+        }
+    }
+
+    // The state change of the instruction, assuming its stack requirements hold
+    fn record(&self, mach: &EvmState, env: &ExecutionEnv) -> MachineRecord<32> {
         match self {
             Instruction::Stop => MachineRecord {
                 halt: true,
@@ -261,6 +825,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 constraints: None,
                 storage: None,
                 pc: (mach.pc(), mach.pc()),
+                ..Default::default()
             },
             Instruction::Add => {
                 let stack = mach.stack();
@@ -285,6 +850,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Mul => {
@@ -299,6 +865,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Sub => {
@@ -313,6 +880,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Div => {
@@ -327,6 +895,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::SDiv => {
@@ -341,6 +910,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::SMod => {
@@ -355,6 +925,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Mod => {
@@ -369,6 +940,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::AddMod => {
@@ -383,6 +955,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::MulMod => {
@@ -397,24 +970,14 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Exp => {
                 let stack = mach.stack();
                 let [a, power] = stack.peek_top().unwrap();
-                let mut power_conc = power.as_ref().as_u64().unwrap();
-                let mut exp: BitVec<32> = if power_conc == 0 {
-                    bvi(1)
-                } else if power_conc == 1 {
-                    a.clone()
-                } else {
-                    let mut temp_exp = a.clone();
-                    while (power_conc) > 1 {
-                        temp_exp = temp_exp.as_ref().bvmul(a.as_ref()).into();
-                        power_conc -= 1;
-                    }
-                    temp_exp
-                };
+                // Checked by `unsupported`
+                let exp = exp(a, power).unwrap();
 
                 let ops = vec![pop(), pop(), push(exp)];
                 MachineRecord {
@@ -424,6 +987,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Lt => {
@@ -444,6 +1008,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Gt => {
@@ -464,6 +1029,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Slt => {
@@ -484,6 +1050,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Sgt => {
@@ -504,6 +1071,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Eq => {
@@ -524,6 +1092,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::And => {
@@ -540,6 +1109,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Or => {
@@ -556,6 +1126,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Xor => {
@@ -572,6 +1143,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Not => {
@@ -589,9 +1161,38 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
+                }
+            }
+            Instruction::Byte => {
+                let stack = mach.stack();
+                let [i, x] = stack.peek_top().unwrap();
+                // Byte i counts from the most significant; bytes past the word are zero
+                let thirty_one: BitVec<32> = bvi(31);
+                let shift = thirty_one
+                    .as_ref()
+                    .bvsub(i.as_ref())
+                    .bvmul(bvi::<32>(8).as_ref());
+                let byte = x.as_ref().bvlshr(&shift).bvand(bvi::<32>(0xff).as_ref());
+                let byte: BitVec<32> = i
+                    .as_ref()
+                    .bvult(bvi::<32>(32).as_ref())
+                    .ite(&byte, bvi::<32>(0).as_ref())
+                    .simplify()
+                    .into();
+
+                let ops = vec![pop(), pop(), push(byte)];
+
+                MachineRecord {
+                    stack: Some(StackChange::with_ops(ops)),
+                    mem: Default::default(),
+                    pc: (mach.pc(), mach.pc() + self.byte_size()),
+                    constraints: None,
+                    halt: false,
+                    storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::Byte => todo!(),
             Instruction::Shl => {
                 let stack = mach.stack();
                 let [shift, value] = stack.peek_top().unwrap();
@@ -607,6 +1208,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Shr => {
@@ -624,6 +1226,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Sha3 => {
@@ -634,21 +1237,17 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 offsett.simplify();
                 sizee.simplify();
 
-                let mem = mach.mem().read_with_offset(offsett.clone(), sizee.clone());
                 let sz = usize::from(sizee.clone());
-
-                let mut bv: BV<'static> = bitvec_array_to_bv(mem);
-
-                let hashed = sha3(bv.get_size()).apply(&[&bv]);
-
-                let hashed: BitVec<32> = hashed.as_bv().unwrap().into();
+                let mem = mach.mem().read_with_offset(offsett.clone(), sz);
+                let (hash, hashed) = keccak(&mem);
                 let mem_change = MemChange {
                     ops_log: vec![MemOp::Read {
                         idx: offsett.clone(),
+                        size: sz,
                     }],
                 };
                 let stack_change =
-                    StackChange::with_ops(vec![StackOp::Pop, StackOp::Pop, StackOp::Push(hashed)]);
+                    StackChange::with_ops(vec![StackOp::Pop, StackOp::Pop, StackOp::Push(hash)]);
 
                 MachineRecord {
                     stack: Some(stack_change),
@@ -657,6 +1256,8 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    hashes: hashed.into_iter().collect(),
+                    ..Default::default()
                 }
             }
             Instruction::Address => {
@@ -669,16 +1270,13 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             },
             Instruction::Balance => {
-                let stack = mach.stack();
-                let addr = stack.peek().unwrap();
-                let bal = balance()
-                    .apply(&[addr.as_ref(), random_bv_arg::<32>().as_ref()])
-                    .as_bv()
-                    .unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(bal.into())]);
+                let addr = stack_address(mach.stack().peek().unwrap());
+                let balance = mach.world.balances.balance(&addr);
+                let stack_diff = StackChange::with_ops(vec![pop(), push(balance)]);
 
                 MachineRecord {
                     stack: Some(stack_diff),
@@ -687,26 +1285,17 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::Origin => {
-                let stack = mach.stack();
-                let orig = origin().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(orig.into())]);
-
-                MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
-                    pc: (mach.pc(), mach.pc() + self.byte_size()),
-                    constraints: None,
-                    halt: false,
-                    storage: None,
-                }
-            }
-            Instruction::Caller => {
+            Instruction::Origin => exec_push_env(mach, env.origin()),
+            Instruction::Caller => exec_push_env(mach, env.caller()),
+            Instruction::CallValue => exec_push_env(mach, env.callvalue()),
+            Instruction::CallDataLoad => {
                 let stack = mach.stack();
-                let caller = env.caller();//caller().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(caller)]);
+                let offset = stack.peek().unwrap();
+                let call_data = env.calldataload(offset);
+                let stack_diff = StackChange::with_ops(vec![pop(), push(call_data)]);
 
                 MachineRecord {
                     stack: Some(stack_diff),
@@ -715,12 +1304,13 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::CallValue => {
+            Instruction::CallDataSize => {
                 let stack = mach.stack();
-                let call_val = call_value().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![push(call_val.into())]);
+                let call_data_sz = env.calldatasize();
+                let stack_diff: StackChange<32> = StackChange::with_ops(vec![push(call_data_sz)]);
 
                 MachineRecord {
                     stack: Some(stack_diff),
@@ -729,39 +1319,36 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::CallDataLoad => {
+            Instruction::CallDataCopy => {
                 let stack = mach.stack();
-                let offset = stack.peek().unwrap();
-                let call_data = env.calldataload(offset);
-                let stack_diff = StackChange::with_ops(vec![pop(), push(call_data)]);
+                let [dest_offset, offset, size] = stack.peek_top().unwrap();
+                let mut dest_offset = dest_offset.clone();
+                let mut offset = offset.clone();
+                let mut size = size.clone();
+                dest_offset.simplify();
+                offset.simplify();
+                size.simplify();
 
-                MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
-                    pc: (mach.pc(), mach.pc() + self.byte_size()),
-                    constraints: None,
-                    halt: false,
-                    storage: None,
-                }
-            }
-            Instruction::CallDataSize => {
-                let stack = mach.stack();
-                let call_data_sz = env.calldatasize();
-                let stack_diff: StackChange<32> = StackChange::with_ops(vec![push(call_data_sz)]);
+                let mem_ops = copy_to_memory(&dest_offset, &size, |i| {
+                    let offset_add: BitVec<32> = bvi(i as i32);
+                    env.calldata_byte(&offset.as_ref().bvadd(offset_add.as_ref()).into())
+                });
 
                 MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
+                    stack: Some(StackChange::with_ops(vec![pop(), pop(), pop()])),
+                    mem: Some(MemChange { ops_log: mem_ops }),
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::CallDataCopy => todo!(),
-            Instruction::CodeSize => todo!(),
+            // Includes any constructor arguments appended to the code
+            Instruction::CodeSize => exec_push_env(mach, bvi(mach.pgm.bytes.len() as i32)),
             Instruction::CodeCopy => {
                 let stack = mach.stack();
                 let [dest_offset, src_offset, size] = stack.peek_top().unwrap();
@@ -779,25 +1366,21 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 //     mach.mem().m_size());
                 // eprintln!("TOTAL CODE BYTE LEN: {:#?}, TOTAL CODE SIZE: {:#?}", mach.pgm.bytes.len(), mach.pgm.get_size());
 
-                let code_last_idx_to_read = usize::from(src_offset.clone()) + usize::from(size.clone());
-                let code = &mach.pgm.bytes[src_offset.clone().into()..code_last_idx_to_read].to_vec();
-                //eprintln!("CODE SIZE: {:#?}", code.len());
-                let mut i: usize = 0;
-                let mut mem_ops = vec![];
-                loop {
-                    if i >= code.len() {
-                        break;
+                // Code is zero-padded past its end
+                let mem_ops = match src_offset.as_ref().as_u64() {
+                    Some(src_offset) => copy_to_memory(&dest_offset, &size, |i| {
+                        let idx = src_offset as usize + i;
+                        mach.pgm.bytes.get(idx).cloned().unwrap_or_default()
+                    }),
+                    None => {
+                        let code = byte_array(&mach.pgm.bytes);
+                        copy_to_memory(&dest_offset, &size, |i| {
+                            let i: BitVec<32> = bvi(i as i32);
+                            let idx = src_offset.as_ref().bvadd(i.as_ref());
+                            code.select(&idx).as_bv().unwrap().into()
+                        })
                     }
-                    let offset_add: BitVec<32> = bvi(i as i32);
-                    let mut idx = (dest_offset.as_ref().bvadd(offset_add.as_ref())).simplify();
-                    mem_ops.push(
-                        MemOp::WriteByte { idx: idx.into(), val: code.get(i).expect(
-                            &format!("Couldnt get code byte at index {}", i)
-                        ).clone() }
-                    );
-                   
-                    i += 1;
-                }
+                };
                 let stack_change = StackChange::with_ops(
                     vec![
                         StackOp::Pop,
@@ -815,29 +1398,23 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
                 
                 
             },
-            Instruction::GasPrice => {
-                let stack = mach.stack();
-                let price = gas_price().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(price.into())]);
-
-                MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
-                    pc: (mach.pc(), mach.pc() + self.byte_size()),
-                    constraints: None,
-                    halt: false,
-                    storage: None,
-                }
-            }
+            Instruction::GasPrice => exec_push_env(mach, env.gasprice()),
             Instruction::ExtCodeSize => {
-                let stack = mach.stack();
-                let addr = stack.peek().unwrap();
-                let ext_code_sz = call_value().apply(&[addr.as_ref()]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(ext_code_sz.into())]);
+                let addr = stack_address(mach.stack().peek().unwrap());
+                let size: BitVec<32> = match code_at(mach, env, &addr) {
+                    Some(code) => bvi(code.bytes.len() as i32),
+                    None => ext_code_size()
+                        .apply(&[&addr.as_ref().zero_ext(96)])
+                        .as_bv()
+                        .unwrap()
+                        .into(),
+                };
+                let stack_diff = StackChange::with_ops(vec![pop(), push(size)]);
 
                 MachineRecord {
                     stack: Some(stack_diff),
@@ -846,73 +1423,98 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::ExtCodeCopy => todo!(),
-            Instruction::ReturnDataSize => todo!(),
-            Instruction::ReturnDataCopy => todo!(),
-            Instruction::ExtCodeHash => todo!(),
-            Instruction::BlockHash => {
+            Instruction::ExtCodeCopy => {
                 let stack = mach.stack();
-                let blk_hash = block_hash().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(blk_hash.into())]);
+                let [addr, dest_offset, offset, size] = stack.peek_top().unwrap();
+                let addr = stack_address(addr);
+                let mut dest_offset = dest_offset.clone();
+                let mut offset = offset.clone();
+                let mut size = size.clone();
+                dest_offset.simplify();
+                offset.simplify();
+                size.simplify();
 
-                MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
-                    pc: (mach.pc(), mach.pc() + self.byte_size()),
-                    constraints: None,
-                    halt: false,
-                    storage: None,
-                }
-            }
-            Instruction::Coinbase => {
-                let stack = mach.stack();
-                let coin_base = coinbase().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(coin_base.into())]);
+                // Known code read at a symbolic offset is treated like unknown code
+                let code = code_at(mach, env, &addr);
+                let mem_ops = match (code, offset.as_ref().as_u64()) {
+                    // Code is zero-padded past its end
+                    (Some(code), Some(offset)) => copy_to_memory(&dest_offset, &size, |i| {
+                        code.bytes.get(offset as usize + i).cloned().unwrap_or_default()
+                    }),
+                    _ => {
+                        let addr = addr.as_ref().zero_ext(96);
+                        copy_to_memory(&dest_offset, &size, |i| {
+                            let i: BitVec<32> = bvi(i as i32);
+                            let idx = offset.as_ref().bvadd(i.as_ref());
+                            ext_code_byte()
+                                .apply(&[&addr, &idx])
+                                .as_bv()
+                                .unwrap()
+                                .into()
+                        })
+                    }
+                };
 
                 MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
+                    stack: Some(StackChange::with_ops(vec![pop(), pop(), pop(), pop()])),
+                    mem: Some(MemChange { ops_log: mem_ops }),
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::Timestamp => {
-                let stack = mach.stack();
-                let timestmp = timestamp().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(timestmp.into())]);
-
+            Instruction::ReturnDataSize => {
+                let size = mach.return_data.size();
                 MachineRecord {
-                    stack: Some(stack_diff),
+                    stack: Some(StackChange::with_ops(vec![push(size)])),
                     mem: Default::default(),
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::Number => todo!(),
-            Instruction::Difficulty => {
+            Instruction::ReturnDataCopy => {
                 let stack = mach.stack();
-                let difficulty = difficulty().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(difficulty.into())]);
+                let [dest_offset, offset, size] = stack.peek_top().unwrap();
+                let mut dest_offset = dest_offset.clone();
+                let mut offset = offset.clone();
+                let mut size = size.clone();
+                dest_offset.simplify();
+                offset.simplify();
+                size.simplify();
+
+                let mem_ops = copy_to_memory(&dest_offset, &size, |i| {
+                    let i: BitVec<32> = bvi(i as i32);
+                    mach.return_data.byte(&offset.as_ref().bvadd(i.as_ref()).simplify().into())
+                });
 
                 MachineRecord {
-                    stack: Some(stack_diff),
-                    mem: Default::default(),
+                    stack: Some(StackChange::with_ops(vec![pop(), pop(), pop()])),
+                    mem: Some(MemChange { ops_log: mem_ops }),
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::GasLimit => {
-                let stack = mach.stack();
-                let gas_limit = gas_lim().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(gas_limit.into())]);
+            Instruction::ExtCodeHash => {
+                let addr = stack_address(mach.stack().peek().unwrap());
+                let (hash, hashed): (BitVec<32>, _) = match code_at(mach, env, &addr) {
+                    Some(code) => keccak(&code.bytes),
+                    None => {
+                        let hash = ext_code_hash().apply(&[&addr.as_ref().zero_ext(96)]);
+                        (hash.as_bv().unwrap().into(), None)
+                    }
+                };
+                let stack_diff = StackChange::with_ops(vec![pop(), push(hash)]);
 
                 MachineRecord {
                     stack: Some(stack_diff),
@@ -921,12 +1523,14 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    hashes: hashed.into_iter().collect(),
+                    ..Default::default()
                 }
             }
-            Instruction::ChainId => {
+            Instruction::BlockHash => {
                 let stack = mach.stack();
-                let cid = chain_id().apply(&[]).as_bv().unwrap();
-                let stack_diff = StackChange::with_ops(vec![pop(), push(cid.into())]);
+                let number = stack.peek().unwrap();
+                let stack_diff = StackChange::with_ops(vec![pop(), push(env.block_hash(number))]);
 
                 MachineRecord {
                     stack: Some(stack_diff),
@@ -935,10 +1539,19 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
-            Instruction::SelfBalance => todo!(),
-            Instruction::BaseFee => todo!(),
+            Instruction::Coinbase => exec_push_env(mach, env.coinbase()),
+            Instruction::Timestamp => exec_push_env(mach, env.timestamp()),
+            Instruction::Number => exec_push_env(mach, env.number()),
+            Instruction::Difficulty => exec_push_env(mach, env.difficulty()),
+            Instruction::GasLimit => exec_push_env(mach, env.gaslimit()),
+            Instruction::ChainId => exec_push_env(mach, env.chain_id()),
+            Instruction::SelfBalance => {
+                exec_push_env(mach, mach.world.balances.balance(&mach.address))
+            }
+            Instruction::BaseFee => exec_push_env(mach, env.base_fee()),
             Instruction::Pop => {
                 let pc = mach.pc();
                 let stack_rec = StackChange {
@@ -954,6 +1567,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::MLoad => {
@@ -966,7 +1580,10 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 val_mem.simplify();
 
                 let mem_change = MemChange {
-                    ops_log: vec![MemOp::Read { idx: dest.clone() }],
+                    ops_log: vec![MemOp::Read {
+                        idx: dest.clone(),
+                        size: 32,
+                    }],
                 };
 
                 MachineRecord {
@@ -976,6 +1593,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::MStore => {
@@ -1001,6 +1619,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
+                    ..Default::default()
                 }
             }
             Instruction::MStore8 => {
@@ -1031,15 +1650,14 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
+                    ..Default::default()
                 }
             }
             Instruction::SLoad => {
                 let key = mach.stack().peek().unwrap();
                 let storage = mach.storage_read(key);
                 let stack_op_1 = StackOp::Pop;
-                let StorageValue::BV(sval) = storage else {
-                    panic!("Arrays not yet supported");
-                };
+                let StorageValue::BV(sval) = storage;
                 let stack_op_2 = StackOp::Push(sval);
                 let stack_change = StackChange::with_ops(vec![stack_op_1, stack_op_2]);
                 MachineRecord {
@@ -1054,6 +1672,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
                     constraints: None,
                     halt: false,
+                    ..Default::default()
                 }
             }
             Instruction::SStore => {
@@ -1081,14 +1700,14 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
                     constraints: None,
                     halt: false,
+                    ..Default::default()
                 }
             }
             Instruction::Jump => {
-                
                 let jump_dest = mach.stack().peek().unwrap();
-           
-                eprintln!("JUMP DEST: {:#?}", jump_dest);
                 let jump_dest_concrete = jump_dest.as_ref().simplify().as_u64().unwrap() as usize;
+                // Jumping anywhere but a JUMPDEST is an exceptional halt
+                let valid_dest = mach.pgm.is_jumpdest(jump_dest_concrete);
                 let stack_rec = StackChange {
                     pop_qty: 1,
                     push_qty: 0,
@@ -1097,11 +1716,12 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 };
                 MachineRecord {
                     stack: Some(stack_rec),
-                    pc: (mach.pc(), jump_dest_concrete),
+                    pc: (mach.pc(), if valid_dest { jump_dest_concrete } else { mach.pc() }),
                     constraints: None,
                     mem: Default::default(),
-                    halt: false,
+                    halt: !valid_dest,
                     storage: None,
+                    ..Default::default()
                 }
                 
 
@@ -1112,6 +1732,9 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 let jump_dest = mach.stack().peek().unwrap();
                 let cond = mach.stack().peek_nth(1).unwrap();
                 let jump_dest_concrete = jump_dest.as_ref().simplify().as_u64().unwrap() as usize;
+                // Only the taken branch halts on an invalid destination; the executor
+                // builds the fallthrough separately
+                let valid_dest = mach.pgm.is_jumpdest(jump_dest_concrete);
 
                 let bv_zero = BV::from_u64(ctx(), 0, 256_u32);
                 let cond = cond.as_ref()._eq(&bv_zero);
@@ -1126,11 +1749,12 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
 
                 MachineRecord {
                     stack: Some(stack_rec),
-                    pc: (mach.pc(), jump_dest_concrete),
+                    pc: (mach.pc(), if valid_dest { jump_dest_concrete } else { mach.pc() }),
                     constraints: Some(cond),
                     mem: Default::default(),
-                    halt: false,
+                    halt: !valid_dest,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::Pc => {
@@ -1148,12 +1772,11 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     mem: Default::default(),
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::MSize => {
-                let mem = mach.mem();
-                let size = mem.m_size();
-                let ops = vec![push(bvi(size as i32))];
+                let ops = vec![push(mach.mem().msize())];
 
                 let stack = Some(StackChange::with_ops(ops));
 
@@ -1164,11 +1787,13 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Gas => {
-                let gas_arg: BitVec<256> = random_bv_arg();
-                let gas = gas().apply(&[gas_arg.as_ref()]).as_bv().unwrap();
+                // Gas left once GAS itself is paid for
+                let cost: BitVec<32> = bvi(G_BASE as i32);
+                let gas = mach.gas.remaining().as_ref().bvsub(cost.as_ref()).simplify();
                 MachineRecord {
                     stack: Some(StackChange::with_ops(vec![StackOp::Push(gas.into())])),
                     pc: (mach.pc(), mach.pc() + self.byte_size()),
@@ -1176,6 +1801,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
 
             },
@@ -1186,6 +1812,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                 halt: false,
                 storage: None,
                 constraints: None,
+                ..Default::default()
             },
             Instruction::Push1(bv) => {
                 let new_bv = bv.as_ref().zero_ext(31).into();
@@ -1199,6 +1826,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push2(bv) => {
@@ -1213,6 +1841,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push3(bv) => {
@@ -1227,6 +1856,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push4(bv) => {
@@ -1241,6 +1871,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push5(bv) => {
@@ -1255,6 +1886,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push6(bv) => {
@@ -1269,6 +1901,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push7(bv) => {
@@ -1283,6 +1916,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push8(bv) => {
@@ -1297,6 +1931,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push9(bv) => {
@@ -1311,6 +1946,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push10(bv) => {
@@ -1325,6 +1961,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push11(bv) => {
@@ -1339,6 +1976,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push12(bv) => {
@@ -1353,6 +1991,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push13(bv) => {
@@ -1367,6 +2006,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push14(bv) => {
@@ -1381,6 +2021,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push15(bv) => {
@@ -1395,6 +2036,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push16(bv) => {
@@ -1409,6 +2051,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push17(bv) => {
@@ -1423,6 +2066,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push18(bv) => {
@@ -1437,6 +2081,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push19(bv) => {
@@ -1451,6 +2096,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push20(bv) => {
@@ -1465,6 +2111,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push21(bv) => {
@@ -1479,6 +2126,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push22(bv) => {
@@ -1493,6 +2141,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push23(bv) => {
@@ -1507,6 +2156,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push24(bv) => {
@@ -1521,6 +2171,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push25(bv) => {
@@ -1535,6 +2186,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push26(bv) => {
@@ -1549,6 +2201,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push27(bv) => {
@@ -1563,6 +2216,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push28(bv) => {
@@ -1577,6 +2231,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push29(bv) => {
@@ -1591,6 +2246,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push30(bv) => {
@@ -1605,6 +2261,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push31(bv) => {
@@ -1619,6 +2276,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Push32(bv) => {
@@ -1631,6 +2289,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
             Instruction::Dup1 => exec_dup_nth(mach, 1),
@@ -1665,36 +2324,96 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
             Instruction::Swap14 => exec_swap_nth(mach, 14),
             Instruction::Swap15 => exec_swap_nth(mach, 15),
             Instruction::Swap16 => exec_swap_nth(mach, 16),
-            Instruction::Log0 => todo!(),
-            Instruction::Log1 => todo!(),
-            Instruction::Log2 => todo!(),
-            Instruction::Log3 => todo!(),
-            Instruction::Log4 => todo!(),
-            Instruction::Create => todo!(),
-            Instruction::Call => todo!(),
-            Instruction::CallCode => todo!(),
-            Instruction::Return => MachineRecord {
-                mem: None,
-                stack: None,
-                storage: None,
-                pc: (mach.pc(), mach.pc()),
-                constraints: None,
-                halt: true,
-            },
-            Instruction::DelegateCall => todo!(),
-            Instruction::Create2 => todo!(),
-            Instruction::StaticCall => todo!(),
-            Instruction::Revert => MachineRecord {
+            Instruction::Log0 => exec_log(mach, 0),
+            Instruction::Log1 => exec_log(mach, 1),
+            Instruction::Log2 => exec_log(mach, 2),
+            Instruction::Log3 => exec_log(mach, 3),
+            Instruction::Log4 => exec_log(mach, 4),
+            Instruction::Create => exec_create(mach, false),
+            Instruction::Call => exec_call(mach, env, CallKind::Call),
+            Instruction::CallCode => exec_call(mach, env, CallKind::CallCode),
+            Instruction::Return => {
+                let [offset, size] = mach.stack().peek_top().unwrap();
+                MachineRecord {
+                    mem: Some(MemChange {
+                        ops_log: mem_access(offset, size),
+                    }),
+                    stack: None,
+                    storage: None,
+                    pc: (mach.pc(), mach.pc()),
+                    constraints: None,
+                    halt: true,
+                    ..Default::default()
+                }
+            }
+            Instruction::DelegateCall => exec_call(mach, env, CallKind::DelegateCall),
+            Instruction::Create2 => exec_create(mach, true),
+            Instruction::StaticCall => exec_call(mach, env, CallKind::StaticCall),
+            Instruction::Revert => {
+                let [offset, size] = mach.stack().peek_top().unwrap();
+                MachineRecord {
+                    mem: Some(MemChange {
+                        ops_log: mem_access(offset, size),
+                    }),
+                    stack: None,
+                    storage: None,
+                    pc: (mach.pc(), mach.pc()),
+                    constraints: None,
+                    halt: true,
+                    ..Default::default()
+                }
+            }
+            Instruction::Invalid => MachineRecord {
                 mem: None,
                 stack: None,
                 storage: None,
                 pc: (mach.pc(), mach.pc()),
                 constraints: None,
                 halt: true,
+                ..Default::default()
             },
-            Instruction::Invalid => todo!(),
-            Instruction::SelfDestruct => todo!(),
-            Instruction::SignExtend => todo!(),
+            Instruction::SelfDestruct => unreachable!("SELFDESTRUCT is left to `unsupported`"),
+            Instruction::SignExtend => {
+                let stack = mach.stack();
+                let [b, x] = stack.peek_top().unwrap();
+                // Extends the sign bit of the low b + 1 bytes; b >= 31 leaves the word as is
+                let sign_bit = b
+                    .as_ref()
+                    .bvmul(bvi::<32>(8).as_ref())
+                    .bvadd(bvi::<32>(7).as_ref());
+                let one: BitVec<32> = bvi(1);
+                let low_mask = one
+                    .as_ref()
+                    .bvshl(&sign_bit.bvadd(one.as_ref()))
+                    .bvsub(one.as_ref());
+                let negative = x
+                    .as_ref()
+                    .bvlshr(&sign_bit)
+                    .bvand(one.as_ref())
+                    ._eq(one.as_ref());
+                let extended = negative.ite(
+                    &x.as_ref().bvor(&low_mask.bvnot()),
+                    &x.as_ref().bvand(&low_mask),
+                );
+                let extended: BitVec<32> = b
+                    .as_ref()
+                    .bvult(bvi::<32>(31).as_ref())
+                    .ite(&extended, x.as_ref())
+                    .simplify()
+                    .into();
+
+                let ops = vec![pop(), pop(), push(extended)];
+
+                MachineRecord {
+                    stack: Some(StackChange::with_ops(ops)),
+                    mem: Default::default(),
+                    pc: (mach.pc(), mach.pc() + self.byte_size()),
+                    constraints: None,
+                    halt: false,
+                    storage: None,
+                    ..Default::default()
+                }
+            }
             Instruction::Push(bv) => {
                 let stack_change = StackChange {
                     pop_qty: 0,
@@ -1710,6 +2429,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     constraints: None,
                     halt: false,
                     storage: None,
+                    ..Default::default()
                 }
             }
             Instruction::IsZero => {
@@ -1729,6 +2449,7 @@ impl<'ctx> MachineInstruction<'ctx, 32> for Instruction {
                     halt: false,
                     storage: None,
                     constraints: None,
+                    ..Default::default()
                 }
             }
         }
@@ -0,0 +1,134 @@
+use z3_ext::ast::{Ast, Bool, BV};
+use z3_ext::{FuncDecl, Sort};
+
+use crate::conversion::{bitvec_array_to_bv, concrete_byte_vec, keccak256};
+use crate::smt::{ctx, BitVec};
+
+// Symbolic hashes are assumed to lie outside the lowest and highest 2^128 values, so they
+// never collide with small storage slots, nor with small offsets from other hashes
+const HASH_MARGIN_BITS: u32 = 128;
+
+// A hash taken by an instruction
+#[derive(Debug, Clone)]
+pub struct Hashed {
+    input: BV<'static>,
+    output: BV<'static>,
+}
+
+impl Hashed {
+    fn is_symbolic(&self) -> bool {
+        self.output.simplify().as_u64().is_none()
+    }
+}
+
+// The uninterpreted function standing in for keccak256 on inputs of `bits` bits. Every input of
+// the same width goes through the same function, so equal inputs hash to equal values.
+fn keccak_fn(bits: u32) -> FuncDecl<'static> {
+    let ctx = ctx();
+    FuncDecl::new(
+        ctx,
+        format!("keccak256_{}", bits),
+        &[&Sort::bitvector(ctx, bits)],
+        &Sort::bitvector(ctx, 256),
+    )
+}
+
+// Keccak256 of a big-endian byte string, with the hash to record on the path taking it.
+// Concrete inputs are hashed; the hash of a symbolic input is an application of `keccak_fn`,
+// constrained by the `axioms` of the hashes recorded on its path.
+pub fn keccak(input: &[BitVec<1>]) -> (BitVec<32>, Option<Hashed>) {
+    let output: BitVec<32> = match concrete_byte_vec(input) {
+        Some(bytes) => keccak256(&bytes).into(),
+        None => {
+            let input = bitvec_array_to_bv(input.to_vec());
+            keccak_fn(input.get_size()).apply(&[&input]).as_bv().unwrap().into()
+        }
+    };
+    // An empty input can't be compared with anything, so there is nothing to record
+    let hashed = (!input.is_empty()).then(|| Hashed {
+        input: bitvec_array_to_bv(input.to_vec()),
+        output: output.as_ref().clone(),
+    });
+    (output, hashed)
+}
+
+// Assumptions about `hashes`, such as those taken on a path, which hold alongside its path
+// condition
+pub fn axioms(hashes: &[Hashed]) -> Vec<Bool<'static>> {
+    let ctx = ctx();
+    let mut distinct: Vec<&Hashed> = vec![];
+    hashes.iter().for_each(|hashed| {
+        if !distinct.iter().any(|prev| prev.input == hashed.input) {
+            distinct.push(hashed);
+        }
+    });
+    let low = BV::from_u64(ctx, 1, 256).bvshl(&BV::from_u64(ctx, HASH_MARGIN_BITS as u64, 256));
+    let high = low.bvneg();
+    let mut axioms = vec![];
+    for (i, hashed) in distinct.iter().enumerate() {
+        if !hashed.is_symbolic() {
+            continue;
+        }
+        axioms.push(hashed.output.bvuge(&low));
+        axioms.push(hashed.output.bvult(&high));
+        // Hashes are injective: equal outputs only come from equal inputs of the same width.
+        // Pairs of concrete hashes need no axiom.
+        distinct
+            .iter()
+            .enumerate()
+            .filter(|(j, other)| *j != i && (*j > i || !other.is_symbolic()))
+            .for_each(|(_, other)| {
+                let same_output = hashed.output._eq(&other.output);
+                if hashed.input.get_size() == other.input.get_size() {
+                    axioms.push(hashed.input._eq(&other.input).iff(&same_output));
+                } else {
+                    axioms.push(same_output.not());
+                }
+            });
+    }
+    axioms
+}
+
+#[test]
+fn test_concrete_keccak() {
+    let (hash, hashed) = keccak(&[]);
+    assert!(hashed.is_none());
+    assert_eq!(
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+        hex::encode(crate::conversion::concrete_bytes(hash.as_ref()).unwrap())
+    );
+}
+
+#[test]
+fn test_symbolic_keccak_is_consistent() {
+    use z3_ext::{SatResult, Solver};
+    let a = vec![BitVec::<1>::new_const("keccak_test_a"); 32];
+    let b = vec![BitVec::<1>::new_const("keccak_test_b"); 32];
+    // The same input hashes to the same term
+    assert_eq!(keccak(&a).0, keccak(&a).0);
+
+    let ((hash_a, hashed_a), (hash_b, hashed_b)) = (keccak(&a), keccak(&b));
+    let solver = Solver::new(ctx());
+    let hashes = [hashed_a.unwrap(), hashed_b.unwrap()];
+    axioms(&hashes).iter().for_each(|axiom| solver.assert(axiom));
+    solver.push();
+    solver.assert(&hash_a.as_ref()._eq(hash_b.as_ref()));
+    solver.assert(&a[0].as_ref()._eq(b[0].as_ref()).not());
+    assert_eq!(SatResult::Unsat, solver.check());
+    solver.pop(1);
+    // A hash never lands on a small storage slot
+    solver.assert(&hash_a.as_ref()._eq(&BV::from_u64(ctx(), 3, 256)));
+    assert_eq!(SatResult::Unsat, solver.check());
+}
+
+#[test]
+fn test_axioms_cover_only_given_hashes() {
+    let a = vec![BitVec::<1>::new_const("keccak_scope_a"); 32];
+    let b = vec![BitVec::<1>::new_const("keccak_scope_b"); 32];
+    let hashed_a = keccak(&a).1.unwrap();
+    let hashed_b = keccak(&b).1.unwrap();
+    // Range axioms for the one hash, and nothing relating it to the other
+    assert_eq!(2, axioms(&[hashed_a.clone()]).len());
+    // Range axioms for each, and injectivity for the pair
+    assert_eq!(5, axioms(&[hashed_a, hashed_b]).len());
+}
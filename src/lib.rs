@@ -1,9 +1,12 @@
 #![allow(unused)]
 // #![feature(adt_const_params)]
 extern crate z3 as z3_ext;
+pub mod balance;
 pub mod conversion;
 pub mod exec;
+pub mod gas;
 pub mod instruction;
+pub mod keccak;
 pub mod machine;
 pub mod memory;
 pub mod parser;
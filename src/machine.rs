@@ -1,21 +1,28 @@
 use std::collections::HashMap;
 use std::sync::RwLock;
 use std::{borrow::BorrowMut, cell::RefCell, rc::Rc};
+use ruint::aliases::U256;
 use uuid::Uuid;
 
 use z3_ext::{
     ast::{Ast, Bool, Int, BV},
-    AstKind, Config, Context, Model, SatResult, Solver,
+    AstKind, Config, Context, Model, Optimize, SatResult, Solver,
 };
 
-use crate::exec::Execution;
+use crate::exec::deploy::{deployments, run_constructor, Deployment};
+use crate::exec::sequence::TransactionSequence;
+use crate::exec::{Execution, ExploreOptions};
+use crate::gas::GasMeter;
 use crate::instruction::*;
+use crate::keccak;
 use crate::memory::*;
 use crate::parser::Program;
-use crate::state::context::ExecutionEnv;
+use crate::conversion::concrete_bytes;
+use crate::state::context::{EvmResult, ExecutionEnv};
+use crate::state::env::{call_data_byte, call_data_size, call_value, caller};
 use crate::state::evm::EvmState;
 use crate::state::tree::{NodeId, StateTree};
-use crate::storage::{AccountStorage, Address};
+use crate::storage::{AccountStorage, Address, GlobalStorage};
 use crate::traits::{Machine, MachineComponent, MachineInstruction, MachineState};
 use crate::{
     bvc, bvi,
@@ -24,36 +31,266 @@ use crate::{
     stack::Stack,
 };
 
-pub struct ExecutionSummary {
-    reachable: Vec<EvmState>,
+// The reachable terminal states of an execution, each with the conditions on the path to it
+pub struct ExecutionSummary<'ctx> {
+    reachable: Vec<ExecBranch<'ctx>>,
 }
 
-impl Default for ExecutionSummary {
+impl<'ctx> Default for ExecutionSummary<'ctx> {
     fn default() -> Self {
         Self::new()
     }
 }
-impl ExecutionSummary {
+impl<'ctx> ExecutionSummary<'ctx> {
     pub fn new() -> Self {
         Self { reachable: vec![] }
     }
 
     pub fn with_state(state: EvmState) -> Self {
         Self {
-            reachable: vec![state],
+            reachable: vec![(state, vec![])],
         }
     }
 
     pub fn with_states(states: Vec<EvmState>) -> Self {
-        Self { reachable: states }
+        Self {
+            reachable: states.into_iter().map(|state| (state, vec![])).collect(),
+        }
+    }
+
+    pub fn with_branches(branches: Vec<ExecBranch<'ctx>>) -> Self {
+        Self {
+            reachable: branches,
+        }
+    }
+
+    pub fn reachable(&self) -> &[ExecBranch<'ctx>] {
+        &self.reachable
     }
 
-    pub fn falsify(&self, assertion: Bool) -> bool {
-        todo!()
+    // Searches the reachable states for one where `assertion` can be false. The assertion is
+    // built per state, so it can refer to the final stack, memory, storage and result.
+    pub fn falsify(&self, assertion: impl Fn(&EvmState) -> Bool<'ctx>) -> Option<Counterexample> {
+        let solver: Solver<'ctx> = Solver::new(ctx());
+        self.reachable.iter().find_map(|(state, conditions)| {
+            solver.push();
+            conditions.iter().for_each(|c| solver.assert(c));
+            let assertion = assertion(state).not();
+            keccak::axioms(&state.hashes).iter().for_each(|axiom| solver.assert(axiom));
+            solver.assert(&assertion);
+            let counterexample = match solver.check() {
+                SatResult::Sat => solver
+                    .get_model()
+                    .map(|model| Counterexample::from_model(&model, state)),
+                _ => None,
+            };
+            solver.pop(1);
+            counterexample
+        })
     }
 
+    // Least and most gas used by the paths taken when calldata starts with `selector`, over
+    // every input that reaches them. None if no finished path is reachable with the selector.
+    // Gas is counted before refunds, since that is what has to fit under the gas limit.
+    pub fn gas_bounds(&self, selector: [u8; 4]) -> Option<GasBounds> {
+        let ctx = ctx();
+        // The calldata of the transaction a state belongs to starts with the selector
+        let selected = |state: &EvmState| {
+            // Calldata given by the environment is matched as is
+            if let Some(calldata) = state.calldata.as_ref() {
+                if calldata.len() < selector.len() {
+                    return vec![Bool::from_bool(ctx, false)];
+                }
+                return selector
+                    .iter()
+                    .zip(calldata)
+                    .map(|(byte, cd)| cd.as_ref()._eq(&BV::from_u64(ctx, *byte as u64, 8)))
+                    .collect();
+            }
+            let tx = state.transaction;
+            let mut selected = selector
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| {
+                    let idx = BV::from_u64(ctx, i as u64, 256);
+                    call_data_byte(tx, &idx)._eq(&BV::from_u64(ctx, *byte as u64, 8))
+                })
+                .collect::<Vec<_>>();
+            let size = call_data_size(tx).apply(&[]).as_bv().unwrap();
+            selected.push(size.bvuge(&BV::from_u64(ctx, 4, 256)));
+            selected
+        };
+
+        let mut bounds: Option<GasBounds> = None;
+        for (state, conditions) in &self.reachable {
+            // Paths cut short by the exploration budget only give a lower bound
+            if matches!(state.result, None | Some(EvmResult::Incomplete { .. })) {
+                continue;
+            }
+            let selected = selected(state);
+            let used: &BV<'ctx> = state.gas.used().as_ref();
+            let optimize = |maximize: bool| {
+                let optimize: Optimize<'ctx> = Optimize::new(ctx);
+                conditions.iter().for_each(|c| optimize.assert(c));
+                selected.iter().for_each(|c| optimize.assert(c));
+                keccak::axioms(&state.hashes).iter().for_each(|axiom| optimize.assert(axiom));
+                if maximize {
+                    optimize.maximize(used);
+                } else {
+                    optimize.minimize(used);
+                }
+                match optimize.check(&[]) {
+                    SatResult::Sat => optimize.get_model(),
+                    _ => None,
+                }
+            };
+            let (min_model, max_model) = match (optimize(false), optimize(true)) {
+                (Some(min_model), Some(max_model)) => (min_model, max_model),
+                _ => continue,
+            };
+            let eval = |model: &Model<'ctx>| {
+                model
+                    .eval(used, true)
+                    .and_then(|val| concrete_bytes(&val))
+                    .and_then(|bytes| U256::try_from_be_slice(&bytes))
+                    .unwrap_or_default()
+            };
+            let (min, max) = (eval(&min_model), eval(&max_model));
+            let out_of_gas = matches!(state.result, Some(EvmResult::OutOfGas));
+            bounds = Some(match bounds {
+                Some(mut prev) => {
+                    prev.min = prev.min.min(min);
+                    prev.out_of_gas |= out_of_gas;
+                    if max > prev.max {
+                        prev.max = max;
+                        prev.max_witness = Counterexample::from_model(&max_model, state);
+                    }
+                    prev
+                }
+                None => GasBounds {
+                    selector,
+                    min,
+                    max,
+                    max_witness: Counterexample::from_model(&max_model, state),
+                    out_of_gas,
+                },
+            });
+        }
+        bounds
+    }
+
+    // The state `steps` instructions before each reachable state, under the same path condition
     pub fn rewind(&self, steps: usize) -> Self {
-        todo!()
+        let reachable = self
+            .reachable
+            .iter()
+            .map(|(state, conditions)| {
+                let mut state = state.clone();
+                state.rewind(steps);
+                (state, conditions.clone())
+            })
+            .collect();
+        Self { reachable }
+    }
+}
+
+// Inputs under which a reachable state violates an assertion
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    // Inputs of the transaction the violating state belongs to
+    pub calldata: Vec<u8>,
+    pub caller: U256,
+    pub callvalue: U256,
+    // Inputs of every transaction of the sequence leading to the violating state, in order.
+    // Outside of a sequence this only holds the inputs above.
+    pub transactions: Vec<TransactionInputs>,
+    // The violating state
+    pub state: EvmState,
+    // The full model, including any environment values the state depends on
+    pub model: String,
+}
+
+// Inputs of a single transaction, read back from a model
+#[derive(Debug, Clone, Default)]
+pub struct TransactionInputs {
+    pub calldata: Vec<u8>,
+    pub caller: U256,
+    pub callvalue: U256,
+}
+
+// Gas used by the paths a function selector can take
+#[derive(Debug, Clone)]
+pub struct GasBounds {
+    pub selector: [u8; 4],
+    pub min: U256,
+    pub max: U256,
+    // Inputs of a path using `max` gas
+    pub max_witness: Counterexample,
+    // Whether some path for the selector runs out of gas, in which case `max` is the gas limit
+    pub out_of_gas: bool,
+}
+
+// Calldata beyond this size is not read back from a model
+pub const MAX_COUNTEREXAMPLE_CALLDATA: u64 = 4096;
+
+impl Counterexample {
+    fn from_model<'ctx>(model: &Model<'ctx>, state: &EvmState) -> Self {
+        let mut transactions = (0..=state.transaction)
+            .map(|tx| TransactionInputs::from_model(model, tx))
+            .collect::<Vec<_>>();
+        // Calldata given by the environment is what the transaction ran with, whatever the
+        // model says of the symbolic calldata
+        if let (Some(calldata), Some(last)) = (state.calldata.as_ref(), transactions.last_mut()) {
+            last.calldata = calldata
+                .iter()
+                .map(|byte| {
+                    model
+                        .eval(byte.as_ref(), true)
+                        .and_then(|val| val.as_u64())
+                        .unwrap_or_default() as u8
+                })
+                .collect();
+        }
+        let TransactionInputs {
+            calldata,
+            caller,
+            callvalue,
+        } = transactions.last().cloned().unwrap_or_default();
+        Self {
+            calldata,
+            caller,
+            callvalue,
+            transactions,
+            state: state.clone(),
+            model: model.to_string(),
+        }
+    }
+}
+
+impl TransactionInputs {
+    fn from_model<'ctx>(model: &Model<'ctx>, tx: usize) -> Self {
+        let eval = |bv: BV<'ctx>| -> U256 {
+            model
+                .eval(&bv, true)
+                .and_then(|val| concrete_bytes(&val))
+                .and_then(|bytes| U256::try_from_be_slice(&bytes))
+                .unwrap_or_default()
+        };
+        let calldatasize: u64 = eval(call_data_size(tx).apply(&[]).as_bv().unwrap())
+            .try_into()
+            .unwrap_or(MAX_COUNTEREXAMPLE_CALLDATA)
+            .min(MAX_COUNTEREXAMPLE_CALLDATA);
+        let calldata = (0..calldatasize)
+            .map(|i| {
+                let byte = call_data_byte(tx, &BV::from_u64(ctx(), i, 256));
+                eval(byte).as_limbs()[0] as u8
+            })
+            .collect();
+        Self {
+            calldata,
+            caller: eval(caller(tx).apply(&[]).as_bv().unwrap()),
+            callvalue: eval(call_value(tx).apply(&[]).as_bv().unwrap()),
+        }
     }
 }
 
@@ -76,7 +313,8 @@ pub struct Evm<'ctx> {
     pub states: StateTree<'ctx>,
     change_log: Vec<MachineRecord<32>>,
     pub inverse_state: HashMap<Uuid, Uuid>,
-    ctx: RwLock<ExecutionEnv<'ctx>>
+    ctx: RwLock<ExecutionEnv<'ctx>>,
+    options: ExploreOptions,
 }
 
 impl<'ctx> Evm<'ctx> {
@@ -90,21 +328,64 @@ impl<'ctx> Evm<'ctx> {
                 path_condition: None,
                 left: None,
                 right: None,
+                forks: vec![],
             },
             change_log: vec![],
             inverse_state: Default::default(),
-            ctx: RwLock::new(ExecutionEnv::default())
+            ctx: RwLock::new(ExecutionEnv::default()),
+            options: Default::default(),
         }
     }
 
     pub fn set_init_state(&mut self, state: EvmState) {
         self.states.val = state;
     }
+
+    pub fn with_options(mut self, options: ExploreOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Selects how the initial state, and every frame it calls into, represents memory
+    pub fn with_memory_model(mut self, model: MemoryModel) -> Self {
+        self.states.val.memory = Memory::with_model(model);
+        self
+    }
+
+    // Gas available to the initial state. Paths needing more end in an out-of-gas leaf.
+    pub fn with_gas_limit(mut self, limit: u64) -> Self {
+        self.states.val.gas = GasMeter::with_limit(limit);
+        self
+    }
+
+    // Treats the program as init code and runs it as the constructor of the initial state's
+    // account. Returns the contract deployed by each feasible path that returns code; one whose
+    // code isn't concrete is returned as an incomplete deployment.
+    pub fn deploy(&self) -> Vec<Deployment> {
+        let env = self.ctx.read().unwrap();
+        let paths = run_constructor(&self.states.val, self.pgm.clone(), &env, &self.options);
+        deployments(&paths)
+    }
+
+    // Treats the program as init code: runs it as the constructor, then explores
+    // `transactions` calls to the contract it deploys, each with symbolic inputs. Every
+    // transaction starts from the initial state's gas limit and memory model.
+    pub fn exec_sequence(&self, transactions: usize) -> TransactionSequence {
+        let env = self.ctx.read().unwrap();
+        TransactionSequence::explore(
+            &self.states.val,
+            self.pgm.clone(),
+            &env,
+            &self.options,
+            transactions,
+        )
+    }
 }
 
 impl<'ctx> Evm<'ctx> {
     pub fn new(pgm: Program, env: ExecutionEnv<'ctx>) -> Self {
-        let evm_state = EvmState::with_pgm(pgm.clone());
+        let mut evm_state = EvmState::with_pgm(pgm.clone());
+        evm_state.world.balances = env.initial_balances();
 
         Self {
             pgm,
@@ -114,10 +395,12 @@ impl<'ctx> Evm<'ctx> {
                 path_condition: None,
                 left: None,
                 right: None,
+                forks: vec![],
             },
             change_log: vec![],
             inverse_state: Default::default(),
-            ctx: RwLock::new(env)
+            ctx: RwLock::new(env),
+            options: Default::default(),
         }
     }
 
@@ -139,6 +422,7 @@ impl<'ctx> Evm<'ctx> {
         Option<String>,
     )> {
         let mut solver = z3_ext::Solver::new(ctx());
+        let states = trace.states.clone();
         let paths = Self::paths(trace);
         let reachable = paths
             .into_iter()
@@ -150,6 +434,13 @@ impl<'ctx> Evm<'ctx> {
                         solver.assert(&constraint);
                     }
                 });
+                // The last state of a path holds every hash taken along it
+                let hashes = path
+                    .last()
+                    .and_then(|(id, _, _)| states.find_by_id(id))
+                    .map(|node| node.val.hashes.clone())
+                    .unwrap_or_default();
+                keccak::axioms(&hashes).iter().for_each(|axiom| solver.assert(axiom));
                 match solver.check() {
                     SatResult::Sat => {
                         let model = solver.get_model();
@@ -212,53 +503,20 @@ impl<'ctx> Machine<32> for Evm<'ctx> {
     type State = EvmState;
 
     fn exec(&mut self) -> Execution {
-        let mut halt = false;
-        let mut step_recs = vec![];
-        let mut exec = Execution::new(self.states.val.clone(), self.pgm.clone());
-        let first_step = exec.step_mut(self.ctx.read().as_ref().unwrap());
-        step_recs.push(first_step);
-        let mut ids = vec![];
-        loop {
-            if let Some(step) = step_recs.pop() {
-                // eprintln!(
-                //     "HALTED LEFT: {}, HALTED RIGHT: {}",
-                //     step.halted_left(),
-                //     step.halted_right()
-                // );
-                // eprintln!(
-                //     "LEFT ID: {:#?} RIGHT ID: {:#?}",
-                //     step.left_id(),
-                //     step.right_id()
-                // );
-                // if !step.halted_right() {
-                //     let continue_from_right = step.right_id();
-                if let Some(right_id) = step.right_id() {
-                    ids.push(right_id.id());
-                    let nxt_right_step = exec.step_from_mut(right_id, self.ctx.read().as_ref().unwrap());
-                    step_recs.push(nxt_right_step);
-                }
-                //}
-                // if !step.halted_left() {
-                //     let continue_from_left = step.left_id();
-                if let Some(left_id) = step.left_id() {
-                    ids.push(left_id.id());
-                    let nxt_step = exec.step_from_mut(left_id, self.ctx.read().as_ref().unwrap());
-                    step_recs.push(nxt_step);
-                }
-                // }
-
-                if step.halted_left() && step.halted_right() {
-                    eprintln!(
-                        "Both have halted... Here are the step recs left: {:#?}",
-                        step_recs
-                    );
-                }
-            } else {
-                break;
-            }
-        }
-        eprintln!("All ids that were executed during a step: {:#?}", ids);
-
+        let env = self.ctx.read().unwrap();
+        let mut start = self.states.val.clone();
+        let can_pay = start.begin_transaction(&env);
+        let mut exec =
+            Execution::new(start, self.pgm.clone()).with_options(self.options.clone());
+        // Transactions whose sender can't pay the callvalue are never executed. A condition on
+        // the initial state, e.g. that of the constructor path that deployed the contract, holds
+        // throughout as well.
+        exec.states.path_condition = match (self.states.path_condition.clone(), can_pay) {
+            (cond, can_pay) if can_pay.as_bool() == Some(true) => cond,
+            (Some(cond), can_pay) => Some(Bool::and(ctx(), &[&cond, &can_pay])),
+            (None, can_pay) => Some(can_pay),
+        };
+        exec.run(&env);
         exec
     }
 
@@ -383,20 +641,21 @@ impl MachineState<32> for EvmState {
         self.memory.apply_change(mem_rec);
     }
 
-    fn storage(&self) -> &AccountStorage {
-        &self.storage
+    fn storage(&self) -> &GlobalStorage {
+        &self.world
     }
 
+    // Reads and writes go to the executing account
     fn storage_write(&mut self, idx: Index, val: crate::storage::StorageValue) {
-        self.storage.sstore(idx, val);
+        self.world.account_mut(&self.address).sstore(idx, val);
     }
 
     fn storage_read(&self, idx: &Index) -> crate::storage::StorageValue {
-        self.storage.sload(idx)
+        self.world.sload(&self.address, idx)
     }
 
     fn storage_apply(&mut self, storage_rec: StorageChange) {
-        self.storage.apply_change(storage_rec);
+        self.world.apply_change(storage_rec);
     }
 }
 
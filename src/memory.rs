@@ -2,16 +2,122 @@ use crate::bvi;
 use ruint::aliases::U1;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use z3_ext::ast::{Ast, BV};
+use z3_ext::ast::{Array, Ast, BV};
+use z3_ext::Sort;
 
+use crate::conversion::bitvec_array_to_bv;
 use crate::record::{Index, MemChange, MemOp};
-use crate::smt::{BVType, BitVec, SolverType};
-use crate::traits::MachineComponent;
+use crate::smt::{ctx, BitVec};
+use crate::traits::{Invertible, MachineComponent};
+
+// Bytes copied by a copy of symbolic length. Paths on which the length may exceed the bound
+// are left incomplete.
+pub const SYMBOLIC_COPY_BOUND: usize = 256;
+
+// How memory is represented
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoryModel {
+    // A vector of bytes; offsets and sizes must be concrete
+    #[default]
+    Concrete,
+    // An SMT array from offset to byte, so offsets and copy sizes may be symbolic
+    Symbolic,
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct Memory {
     pub(crate) inner: Vec<BitVec<1>>,
     highest_idx: usize,
+    // Replaces `inner` and `highest_idx` under the symbolic model
+    symbolic: Option<SymbolicMemory>,
+}
+
+#[derive(Clone, Debug)]
+struct SymbolicMemory {
+    // All-zero memory with every write in `writes` stored into it
+    bytes: Array<'static>,
+    // Byte writes in the order they were made
+    writes: Vec<(BV<'static>, BV<'static>)>,
+    // One past the highest offset accessed
+    m_size: BV<'static>,
+}
+
+impl SymbolicMemory {
+    fn new() -> Self {
+        let ctx = ctx();
+        Self {
+            bytes: Self::zeroed(),
+            writes: vec![],
+            m_size: BV::from_u64(ctx, 0, 256),
+        }
+    }
+
+    fn zeroed() -> Array<'static> {
+        let ctx = ctx();
+        Array::const_array(ctx, &Sort::bitvector(ctx, 256), &BV::from_u64(ctx, 0, 8))
+    }
+
+    fn select(&self, idx: &BV<'static>) -> BV<'static> {
+        self.bytes.select(idx).as_bv().unwrap()
+    }
+
+    fn store(&mut self, idx: BV<'static>, val: BV<'static>) {
+        self.bytes = self.bytes.store(&idx, &val);
+        self.writes.push((idx, val));
+    }
+
+    // Expands memory to cover every byte below `end`, rounded up to a whole word
+    fn grow_to(&mut self, end: BV<'static>) {
+        let ctx = ctx();
+        let mask = BV::from_u64(ctx, 31, 256);
+        let end = end.bvadd(&mask).bvand(&mask.bvnot());
+        self.m_size = end.bvugt(&self.m_size).ite(&end, &self.m_size).simplify();
+    }
+
+    fn apply(&mut self, op: MemOp) {
+        let ctx = ctx();
+        match op {
+            MemOp::Write { idx, val } => {
+                let idx = idx.as_ref().simplify();
+                // Big-endian: the most significant byte goes to the lowest offset
+                for i in 0..32_u32 {
+                    let hi = (31 - i) * 8 + 7;
+                    let byte = val.as_ref().extract(hi, hi - 7).simplify();
+                    let at = idx.bvadd(&BV::from_u64(ctx, i as u64, 256)).simplify();
+                    self.store(at, byte);
+                }
+                self.grow_to(idx.bvadd(&BV::from_u64(ctx, 32, 256)));
+            }
+            MemOp::WriteByte { idx, val } => {
+                let idx = idx.as_ref().simplify();
+                self.store(idx.clone(), val.as_ref().clone());
+                self.grow_to(idx.bvadd(&BV::from_u64(ctx, 1, 256)));
+            }
+            MemOp::WriteByteIf { idx, val, cond } => {
+                let idx = idx.as_ref().simplify();
+                let byte = cond.ite(val.as_ref(), &self.select(&idx));
+                self.store(idx.clone(), byte);
+                let end = idx.bvadd(&BV::from_u64(ctx, 1, 256));
+                let grown = cond.ite(&end, &self.m_size);
+                self.grow_to(grown);
+            }
+            MemOp::Read { idx, size } => {
+                if size > 0 {
+                    let size = BV::from_u64(ctx, size as u64, 256);
+                    self.grow_to(idx.as_ref().bvadd(&size));
+                }
+            }
+            MemOp::Truncate { writes, m_size } => {
+                self.writes.truncate(writes);
+                self.bytes = self
+                    .writes
+                    .iter()
+                    .fold(Self::zeroed(), |bytes, (idx, val)| bytes.store(idx, val));
+                self.m_size = m_size.as_ref().clone();
+            }
+            MemOp::Restore { .. } => {}
+        }
+    }
 }
 
 impl MachineComponent for Memory {
@@ -19,44 +125,118 @@ impl MachineComponent for Memory {
 
     fn apply_change(&mut self, rec: Self::Record) {
         let MemChange { ops_log } = rec;
-        let mut highest_idx = self.highest_idx;
+        if let Some(symbolic) = self.symbolic.as_mut() {
+            ops_log.into_iter().for_each(|op| symbolic.apply(op));
+            return;
+        }
         ops_log.into_iter().for_each(|op| match op {
             MemOp::Write { val, idx } => {
                 let mut val = val;
                 val.simplify();
                 let mut idx = idx;
                 idx.simplify();
-                // eprintln!(
-                //     "MEM WRITE FOR MEM APPLY: idx: {:#?}, value: {:#?}",
-                //     idx, val
-                // );
-                let mut idx_cmp: usize = idx.clone().into();
-                idx_cmp += 32;
-                if idx_cmp > highest_idx {
-                    highest_idx = idx_cmp;
-                }
                 self.write_word(idx, val);
             }
-            MemOp::Read { idx } => {
-                let idx_cmp: usize = idx.into();
-                if idx_cmp > highest_idx {
-                    highest_idx = idx_cmp;
+            MemOp::Read { idx, size } => {
+                if size > 0 {
+                    self.expand(usize::from(idx) + size);
                 }
             }
             MemOp::WriteByte { idx, val } => {
-                let mut idx_cmp: usize = idx.clone().into();
-                idx_cmp += 1;
-                if idx_cmp > highest_idx {
-                    highest_idx = idx_cmp;
-                }
                 self.write(idx, val);
             }
+            MemOp::WriteByteIf { idx, val, cond } => {
+                let cond = cond.simplify();
+                if cond.as_bool() == Some(false) {
+                    return;
+                }
+                let prev = self.read(idx.clone());
+                let val = cond.ite(val.as_ref(), prev.as_ref()).simplify();
+                self.write(idx, val.into());
+            }
+            MemOp::Restore {
+                idx,
+                prev,
+                len,
+                m_size,
+            } => {
+                self.inner.truncate(len);
+                self.inner.splice(idx..idx + prev.len(), prev);
+                self.highest_idx = m_size;
+            }
+            MemOp::Truncate { .. } => {}
         });
-        self.highest_idx = highest_idx;
+    }
+}
+
+impl Invertible for Memory {
+    fn inverse_of(&self, rec: &Self::Record) -> Self::Record {
+        if let Some(symbolic) = self.symbolic.as_ref() {
+            return MemChange {
+                ops_log: vec![MemOp::Truncate {
+                    writes: symbolic.writes.len(),
+                    m_size: symbolic.m_size.clone().into(),
+                }],
+            };
+        }
+        // Only the bytes between the lowest and highest index written to are kept; anything
+        // past the current end is dropped again by truncating
+        let written = rec
+            .ops_log
+            .iter()
+            .filter_map(|op| match op {
+                MemOp::Write { idx, .. } => {
+                    let idx = usize::from(idx.clone());
+                    Some((idx, idx.saturating_add(32)))
+                }
+                MemOp::WriteByte { idx, .. } | MemOp::WriteByteIf { idx, .. } => {
+                    let idx = usize::from(idx.clone());
+                    Some((idx, idx.saturating_add(1)))
+                }
+                MemOp::Restore { idx, .. } => Some((*idx, self.size())),
+                MemOp::Read { .. } | MemOp::Truncate { .. } => None,
+            })
+            .reduce(|(lo, hi), (idx, end)| (lo.min(idx), hi.max(end)));
+        let (lowest, highest) = written.unwrap_or((self.size(), self.size()));
+        let lowest = lowest.min(self.size());
+        let highest = highest.clamp(lowest, self.size());
+        MemChange {
+            ops_log: vec![MemOp::Restore {
+                idx: lowest,
+                prev: self.inner[lowest..highest].to_vec(),
+                len: self.size(),
+                m_size: self.highest_idx,
+            }],
+        }
     }
 }
 
 impl Memory {
+    pub fn with_model(model: MemoryModel) -> Self {
+        match model {
+            MemoryModel::Concrete => Self::default(),
+            MemoryModel::Symbolic => Self {
+                symbolic: Some(SymbolicMemory::new()),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn model(&self) -> MemoryModel {
+        if self.symbolic.is_some() {
+            MemoryModel::Symbolic
+        } else {
+            MemoryModel::Concrete
+        }
+    }
+
+    // MSIZE; symbolic once the symbolic model is accessed at a symbolic offset
+    pub fn msize(&self) -> BitVec<32> {
+        match self.symbolic.as_ref() {
+            Some(symbolic) => symbolic.m_size.clone().into(),
+            None => bvi(self.highest_idx as i32),
+        }
+    }
 
     pub fn memory(&self) -> Vec<BitVec<1>> {
         self.inner[0..self.m_size()].to_vec()
@@ -69,15 +249,38 @@ impl Memory {
         self.highest_idx
     }
 
+    // Size of memory in 32-byte words after accessing `size` bytes at `offset`, as charged for
+    // by memory expansion gas. None when the size of memory or the access is symbolic.
+    pub fn words_after(&self, offset: &Index, size: &Index) -> Option<usize> {
+        let m_size = self.msize().as_ref().simplify().as_u64()? as usize;
+        let size = size.as_ref().simplify().as_u64()? as usize;
+        if size == 0 {
+            return Some(m_size / 32);
+        }
+        let offset = offset.as_ref().simplify().as_u64()? as usize;
+        Some(m_size.max(word_ceil(offset.saturating_add(size))) / 32)
+    }
+
+    // Zero-fills memory so it covers every byte below `end`, rounded up to a whole word
+    fn expand(&mut self, end: usize) {
+        let end = word_ceil(end);
+        if end > self.inner.len() {
+            self.inner.resize(end, BitVec::default());
+        }
+        self.highest_idx = self.highest_idx.max(end);
+    }
+
     pub fn write(&mut self, idx: Index, val: BitVec<1>) {
-        let idx = idx.into();
-        self.inner.insert(idx, val);
-      
+        let idx: usize = idx.into();
+        self.expand(idx + 1);
+        self.inner[idx] = val;
     }
     pub fn read(&self, idx: Index) -> BitVec<1> {
+        if let Some(symbolic) = self.symbolic.as_ref() {
+            return symbolic.select(idx.as_ref()).into();
+        }
         let idx: usize = idx.into();
-        let val = self.inner.get(idx).unwrap().clone();
-        val
+        self.inner.get(idx).cloned().unwrap_or_default()
     }
 
     pub fn read_with_offset(
@@ -85,63 +288,50 @@ impl Memory {
         offset: Index,
         size: impl Into<usize> + Clone,
     ) -> Vec<BitVec<1>> {
-        let idx: usize = offset.into();
+        self.read_bytes(offset, BitVec::new_literal(size.into() as u64))
+    }
 
-       // eprintln!("IDX: {idx:} and size: {:#?}", size.clone().into());
-        let val = self.inner[idx..(idx + size.clone().into())].to_vec();
-       // eprintln!("VAL IN MEM READ EITH OFFSET: {:#?}", val);
-        val
+    // Reads `size` bytes at `offset`; bytes past the end of memory are read as zero
+    pub fn read_bytes(&self, offset: Index, size: Index) -> Vec<BitVec<1>> {
+        if let Some(symbolic) = self.symbolic.as_ref() {
+            // The offset may be symbolic, but the number of bytes read may not
+            let size: usize = size.into();
+            return (0..size)
+                .map(|i| {
+                    let i: BitVec<32> = bvi(i as i32);
+                    let idx = offset.as_ref().bvadd(i.as_ref()).simplify();
+                    symbolic.select(&idx).simplify().into()
+                })
+                .collect();
+        }
+        let offset: usize = offset.into();
+        let size: usize = size.into();
+        (offset..offset + size)
+            .map(|i| self.inner.get(i).cloned().unwrap_or_default())
+            .collect()
     }
+
+    // The big-endian word at `idx`
     pub fn read_word(&self, idx: Index) -> BitVec<32> {
-        let mut i = 0;
-        let idx: usize = idx.into();
-        let mut bytes = vec![];
-        let mut mem = self.inner.clone();
-        // eprintln!(" MEM IN READ WORD: {:#?}", mem);
-        while i < 32 {
-            let idx = idx + 31;
-
-            let val = mem.get(idx - i).unwrap().as_ref().clone();
-            //eprintln!("MEM VAL IN READ WORD FOR IDX - i:\nmem loc {:#?}\nval: {:#?}", (idx - i), val);
-            bytes.push(val);
-            i += 1;
-        }
-        let mut new_bv: BitVec<1> = BitVec::default();
-        let mut new_bv_inner = new_bv.as_ref().clone();
-        bytes.into_iter().enumerate().for_each(|(i, b)| {
-            if i == 0 {
-                new_bv = BitVec::with_bv(b);
-                new_bv_inner = new_bv.as_ref().clone();
-            } else {
-                new_bv_inner = new_bv_inner.concat(&b);
-            }
-        });
-        BitVec {
-            inner: BVType::Z3(new_bv_inner),
-            typ: SolverType::Z3,
-        }
+        let bytes = self.read_bytes(idx, bvi(32));
+        bitvec_array_to_bv(bytes).into()
     }
 
     pub fn write_word(&mut self, idx: Index, word: BitVec<32>) {
-        let idx = idx.into();
-        if idx > self.size() {
-            for i in 0..idx - self.size() {
-                self.inner.push(BitVec::default());
-            }
-        }
-        //eprintln!("WORD: {word:#?}");
+        let idx: usize = idx.into();
+        self.expand(idx + 32);
         for i in 0..32 {
-            let ii = 32 - i - 1;
-            let bv = word.as_ref().extract(ii * 8 + 7, ii * 8).simplify();
-            // eprintln!("Extracted: {:#?}", bv);
-
-            let bv: BitVec<1> = bv.into();
-            //eprintln!("Extracted size: {:#?}", bv.as_ref().get_size());
-            self.inner.insert(idx, bv);
+            let hi = (31 - i as u32) * 8 + 7;
+            let bv = word.as_ref().extract(hi, hi - 7).simplify();
+            self.inner[idx + i] = bv.into();
         }
     }
 }
 
+fn word_ceil(n: usize) -> usize {
+    n.saturating_add(31) / 32 * 32
+}
+
 impl std::fmt::Display for Memory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut mem_str = format!(
@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use crate::conversion::concrete_bytes;
 use crate::{bvi, instruction::*, smt::BitVec};
 use hex::decode;
 use revm::{opcode::OpCode, OPCODE_JUMPMAP};
@@ -27,6 +28,7 @@ impl<'a> Parser<'a> {
         let mut idx = 0_usize;
         let mut pgm_map = HashMap::new();
         let mut pgm_bytes = vec![];
+        let mut jumpdests = HashSet::new();
         for b in &bytes {
             let b = *b;
             let b_bv: [u8;1] = [b];
@@ -55,6 +57,10 @@ impl<'a> Parser<'a> {
                 pgm.push(inst);
             } else {
                 let inst = Instruction::from(b);
+                // Push data was skipped above, so a 0x5b byte here is a real JUMPDEST
+                if inst == Instruction::JumpDest {
+                    jumpdests.insert(idx);
+                }
                 pgm_map.insert(idx, inst.clone());
                 pgm.push(inst);
             }
@@ -64,7 +70,8 @@ impl<'a> Parser<'a> {
             map: pgm_map,
             pgm,
             size: idx + 1,
-            bytes: pgm_bytes
+            bytes: pgm_bytes,
+            jumpdests,
         }
     }
 }
@@ -536,7 +543,8 @@ pub struct Program {
     pub map: HashMap<usize, Instruction>,
     pgm: Vec<Instruction>,
     pub size: usize,
-    pub bytes: Vec<BitVec<1>>
+    pub bytes: Vec<BitVec<1>>,
+    jumpdests: HashSet<usize>,
 }
 
 impl Program {
@@ -547,6 +555,53 @@ impl Program {
     pub fn get_size(&self) -> usize {
         self.size
     }
+
+    // Appends constructor arguments, concrete or symbolic, to init code. They can be read with
+    // CODESIZE and CODECOPY but are never executed.
+    pub fn with_args(mut self, args: Vec<BitVec<1>>) -> Self {
+        self.bytes.extend(args);
+        self
+    }
+
+    // Offsets of every JUMPDEST instruction, in order
+    pub fn jumpdests(&self) -> Vec<usize> {
+        let mut dests = self.jumpdests.iter().copied().collect::<Vec<_>>();
+        dests.sort();
+        dests
+    }
+
+    // Whether `pc` is a JUMPDEST opcode, as opposed to any other opcode or push data
+    pub fn is_jumpdest(&self, pc: usize) -> bool {
+        self.jumpdests.contains(&pc)
+    }
+
+    // Function selectors checked by a solc-style dispatcher, in the order they are checked.
+    // Each one is compared for equality right before a jump to its function:
+    // PUSH4 selector, optionally DUPn, EQ, PUSHn dest, JUMPI
+    pub fn selectors(&self) -> Vec<[u8; 4]> {
+        let mut selectors = vec![];
+        for (i, inst) in self.pgm.iter().enumerate() {
+            let selector = match inst {
+                Instruction::Push4(selector) => selector,
+                _ => continue,
+            };
+            let mut rest = self.pgm[i + 1..].iter().peekable();
+            rest.next_if(|inst| {
+                matches!(inst, Instruction::Dup1 | Instruction::Dup2 | Instruction::Dup3)
+            });
+            let is_dispatch = matches!(rest.next(), Some(Instruction::Eq))
+                && rest.next().map_or(false, |inst| inst.byte_size() > 1)
+                && matches!(rest.next(), Some(Instruction::JumpI));
+            let bytes = concrete_bytes(selector.as_ref()).filter(|bytes| bytes.len() == 4);
+            if let (true, Some(bytes)) = (is_dispatch, bytes) {
+                let selector = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                if !selectors.contains(&selector) {
+                    selectors.push(selector);
+                }
+            }
+        }
+        selectors
+    }
 }
 
 #[test]
@@ -564,6 +619,25 @@ fn is_push_works() {
     }
 }
 
+#[test]
+fn jumpdests_skip_push_data() {
+    // PUSH1 0x5b, JUMPDEST, PUSH2 0x5b5b, JUMPDEST
+    let pgm = Parser::with_pgm("605b5b615b5b5b").parse();
+    assert_eq!(vec![2, 6], pgm.jumpdests());
+    assert!(!pgm.is_jumpdest(1));
+    assert!(!pgm.is_jumpdest(4));
+}
+
+#[test]
+fn selectors_are_found_in_the_dispatcher() {
+    // DUP1 PUSH4 0x1ab06ee5 EQ PUSH1 0x37 JUMPI, DUP1 PUSH4 0xfac333ac EQ PUSH1 0x56 JUMPI
+    let pgm = Parser::with_pgm("80631ab06ee5146037578063fac333ac14605657").parse();
+    assert_eq!(vec![[0x1a, 0xb0, 0x6e, 0xe5], [0xfa, 0xc3, 0x33, 0xac]], pgm.selectors());
+    // A PUSH4 that is not compared against is not a selector
+    let pgm = Parser::with_pgm("63aabbccdd5000").parse();
+    assert!(pgm.selectors().is_empty());
+}
+
 /**
  * pragma solidity ^0.8.3;
 
@@ -1,12 +1,17 @@
-use crate::smt::BitVec;
-use crate::storage::Address;
+use crate::gas::GasCheckpoint;
+use crate::keccak::Hashed;
+use crate::parser::Program;
+use crate::smt::{ctx, BitVec};
+use crate::state::context::{EvmResult, Log};
+use crate::storage::{AccountStorage, Address, GlobalStorage};
 
 use ruint::aliases::*;
 use ruint::Uint;
 use z3_ext::ast::Ast;
-use z3_ext::ast::Bool;
+use z3_ext::ast::{Array, Bool};
+use z3_ext::Sort;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct MachineRecord<const STACK_ITEM_SZ: usize> {
     pub mem: Option<MemChange>,
     pub stack: Option<StackChange<STACK_ITEM_SZ>>,
@@ -14,6 +19,11 @@ pub struct MachineRecord<const STACK_ITEM_SZ: usize> {
     pub pc: (usize, usize),
     pub constraints: Option<Bool<'static>>,
     pub halt: bool,
+    pub call: Option<CallChange>,
+    pub create: Option<CreateChange>,
+    pub log: Option<Log>,
+    // Hashes taken by the instruction, whose axioms hold on every path it is applied to
+    pub hashes: Vec<Hashed>,
 }
 
 pub type Index = BitVec<32>;
@@ -32,7 +42,20 @@ pub struct MemChange {
 pub enum MemOp {
     Write { idx: Index, val: BitVec<32> },
     WriteByte { idx: Index, val: BitVec<1> },
-    Read { idx: Index },
+    // Accesses `size` bytes at `idx` without changing them, expanding memory to cover them
+    Read { idx: Index, size: usize },
+    // Writes `val` at `idx` only where `cond` holds, as done by copies of symbolic length
+    WriteByteIf { idx: Index, val: BitVec<1>, cond: Bool<'static> },
+    // Truncates memory to `len` bytes, writes `prev` back at `idx` and resets the highest index
+    // to `m_size`
+    Restore {
+        idx: usize,
+        prev: Vec<BitVec<1>>,
+        len: usize,
+        m_size: usize,
+    },
+    // Symbolic memory only: drops every byte write after the first `writes` and resets the size
+    Truncate { writes: usize, m_size: BitVec<32> },
 }
 #[derive(Clone, Debug)]
 pub enum StackOp<const SZ: usize> {
@@ -89,6 +112,14 @@ pub enum StorageOp {
         idx: Index,
         val: Value,
     },
+    // Drops every write to the account after the first `writes` and resets whether `idx` was
+    // touched
+    Restore {
+        addr: Address,
+        idx: Index,
+        writes: usize,
+        touched: bool,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -96,6 +127,137 @@ pub struct StorageChange {
     pub log: Vec<StorageOp>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+    Call,
+    CallCode,
+    DelegateCall,
+    StaticCall,
+}
+
+// A message call made by the current frame. A callee whose code is known is executed as a
+// nested frame and the record carries its outcome; otherwise the outcome is described by a
+// fresh symbolic success flag and fresh symbolic return data.
+#[derive(Clone, Debug)]
+pub struct CallChange {
+    pub kind: CallKind,
+    pub gas: Value,
+    pub target: Address,
+    // None for DELEGATECALL and STATICCALL, which take no value argument
+    pub value: Option<Value>,
+    pub calldata: Vec<BitVec<1>>,
+    pub ret_offset: Index,
+    pub ret_size: Index,
+    pub return_data: ReturnData,
+    pub success: Bool<'static>,
+    // Whether the call sends value to an empty account, bringing it into existence
+    pub creates_account: Bool<'static>,
+}
+
+// Output of a frame's most recent call or creation, as read by RETURNDATASIZE and
+// RETURNDATACOPY
+#[derive(Clone, Debug)]
+pub enum ReturnData {
+    // Output of a frame that was executed
+    Bytes(Vec<BitVec<1>>),
+    // Output of an unknown callee, which may be of any length regardless of the output size
+    // the caller asked for
+    Symbolic { size: BitVec<32>, bytes: Array<'static> },
+}
+
+impl Default for ReturnData {
+    fn default() -> Self {
+        Self::Bytes(vec![])
+    }
+}
+
+impl ReturnData {
+    // Fresh return data of a fresh length, named after `name`
+    pub fn new_const(name: impl AsRef<str>) -> Self {
+        let ctx = ctx();
+        let name = name.as_ref();
+        Self::Symbolic {
+            size: BitVec::new_const(format!("{}_size", name)),
+            bytes: Array::new_const(
+                ctx,
+                name,
+                &Sort::bitvector(ctx, 256),
+                &Sort::bitvector(ctx, 8),
+            ),
+        }
+    }
+
+    pub fn size(&self) -> BitVec<32> {
+        match self {
+            Self::Bytes(bytes) => BitVec::new_literal(bytes.len() as u64),
+            Self::Symbolic { size, .. } => size.clone(),
+        }
+    }
+
+    // Byte `idx` of the data; zero past its end
+    pub fn byte(&self, idx: &Index) -> BitVec<1> {
+        match self {
+            Self::Bytes(bytes) => match idx.as_ref().simplify().as_u64() {
+                Some(idx) => bytes.get(idx as usize).cloned().unwrap_or_default(),
+                None => bytes.iter().enumerate().fold(BitVec::default(), |rest, (i, byte)| {
+                    let i: BitVec<32> = BitVec::new_literal(i as u64);
+                    idx.as_ref()
+                        ._eq(i.as_ref())
+                        .ite(byte.as_ref(), rest.as_ref())
+                        .into()
+                }),
+            },
+            Self::Symbolic { size, bytes } => {
+                let byte = bytes.select(idx.as_ref()).as_bv().unwrap();
+                let zero: BitVec<1> = BitVec::default();
+                idx.as_ref()
+                    .bvult(size.as_ref())
+                    .ite(&byte, zero.as_ref())
+                    .into()
+            }
+        }
+    }
+}
+
+// A contract creation made by the current frame with CREATE or CREATE2
+#[derive(Clone, Debug)]
+pub struct CreateChange {
+    pub value: Value,
+    pub init_code: Vec<BitVec<1>>,
+    // None for CREATE
+    pub salt: Option<Value>,
+    // Derived per the spec when the sender, salt and init code are concrete; symbolic otherwise
+    pub address: Address,
+    // Runtime code returned by the init code, when it was executed and returned concrete bytes
+    pub deployed: Option<Program>,
+    pub return_data: ReturnData,
+    pub success: Bool<'static>,
+}
+
+// The parts of an EvmState that applying a MachineRecord overwrites. Passing it to
+// EvmState::undo restores the state the record was applied to.
+#[derive(Clone, Debug)]
+pub struct InverseRecord {
+    pub stack: Option<StackChange<32>>,
+    pub mem: Option<MemChange>,
+    pub storage: Option<StorageChange>,
+    pub pc: usize,
+    pub halt: bool,
+    pub result: Option<EvmResult>,
+    // Only kept for records that replace the return data, i.e. calls and creations
+    pub return_data: Option<ReturnData>,
+    pub nonce: u64,
+    pub calls_len: usize,
+    pub logs_len: usize,
+    pub transfers_len: usize,
+    pub hashes_len: usize,
+    pub gas: GasCheckpoint,
+    // Address written by a creation, with the account previously there
+    pub created: Option<(Address, Option<AccountStorage>)>,
+    // The world before a nested frame's world was adopted
+    pub world: Option<GlobalStorage>,
+}
+
 pub fn push<const SZ: usize>(val: BitVec<SZ>) -> StackOp<SZ> {
     StackOp::Push(val)
 }
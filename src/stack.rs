@@ -2,12 +2,16 @@ use crate::{bvi, record::StackChange};
 use std::fmt::{Debug, Formatter};
 
 use super::smt::*;
-use crate::traits::MachineComponent;
+use crate::record::StackOp;
+use crate::traits::{Invertible, MachineComponent};
 use smallvec::SmallVec;
 use z3_ext::{
     ast::{Ast, BV},
     Config,
 };
+// Executing an instruction that would leave more items on the stack is an exceptional halt
+pub const MAX_STACK_DEPTH: usize = 1024;
+
 #[derive(Default, Debug, Clone)]
 pub struct Stack<const SZ: usize> {
     stack: SmallVec<[BitVec<SZ>; 1024]>,
@@ -112,7 +116,7 @@ impl<const SZ: usize> MachineComponent for Stack<SZ> {
             crate::record::StackOp::Pop => {
                 new_stack.pop();
             }
-            crate::record::StackOp::Swap(depth) => {}
+            crate::record::StackOp::Swap(depth) => new_stack.swap_nth(*depth),
         });
 
         if swap_depth > 0 {
@@ -129,6 +133,33 @@ impl<const SZ: usize> MachineComponent for Stack<SZ> {
     }
 }
 
+impl<const SZ: usize> Invertible for Stack<SZ> {
+    fn inverse_of(&self, rec: &Self::Record) -> Self::Record {
+        let mut stack = self.clone();
+        let mut inverse = rec
+            .ops
+            .iter()
+            .map(|op| match op {
+                StackOp::Push(v) => {
+                    stack.push(v.clone());
+                    StackOp::Pop
+                }
+                StackOp::Pop => StackOp::Push(stack.pop()),
+                StackOp::Swap(depth) => {
+                    stack.swap_nth(*depth);
+                    StackOp::Swap(*depth)
+                }
+            })
+            .collect::<Vec<_>>();
+        // A record's swap is applied after its ops, so it is undone first
+        if rec.swap_depth > 0 {
+            inverse.push(StackOp::Swap(rec.swap_depth));
+        }
+        inverse.reverse();
+        StackChange::with_ops(inverse)
+    }
+}
+
 #[test]
 fn test_swap() {
     let mut stack: Stack<1> = Stack::default();
@@ -140,3 +171,19 @@ fn test_swap() {
     assert_eq!(stack.peek().cloned().unwrap(), bvi(2));
     assert_eq!(stack.peek_nth(2).cloned().unwrap(), bvi(4));
 }
+
+#[test]
+fn test_stack_inverse() {
+    let mut stack: Stack<1> = Stack::default();
+    stack.push(bvi(1));
+    stack.push(bvi(2));
+    let before = stack.clone();
+    let rec = StackChange::with_ops(vec![StackOp::Pop, StackOp::Pop, StackOp::Push(bvi(3))]);
+    let inverse = stack.inverse_of(&rec);
+    stack.apply_change(rec);
+    assert_eq!(1, stack.size());
+    stack.apply_change(inverse);
+    assert_eq!(before.size(), stack.size());
+    assert_eq!(bvi(2), stack.peek().cloned().unwrap());
+    assert_eq!(bvi(1), stack.peek_nth(1).cloned().unwrap());
+}
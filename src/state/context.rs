@@ -1,11 +1,16 @@
 use std::collections::HashMap;
 
 use ruint::aliases::U256;
-use z3_ext::ast::{Ast, Bool};
+use z3_ext::ast::{Ast, Bool, BV};
+use z3_ext::FuncDecl;
 
-use crate::{smt::BitVec, storage::Address, parser::Program, bvi, conversion::bitvec_array_to_bv, random_bv_arg};
+use crate::balance::Balances;
+use crate::{smt::{ctx, BitVec}, storage::Address, parser::Program, bvi, conversion::bitvec_array_to_bv, random_bv_arg};
 
-use super::env::{call_data_size, call_data_load, caller};
+use super::env::*;
+use super::revert::RevertReason;
+use crate::exec::budget::BudgetExceeded;
+use crate::instruction::InstructionError;
 
 #[derive(Debug, Clone, Default)]
 pub struct ExecutionEnv<'ctx> {
@@ -50,64 +55,353 @@ impl<'ctx> ExecutionEnv<'ctx> {
         self
     } 
 
+    pub fn set_calldata_bytes(mut self, calldata: Vec<BitVec<1>>) -> Self {
+        self.tx.calldata = Some(calldata);
+        self
+    }
+
+    pub fn set_callvalue(mut self, callvalue: BitVec<32>) -> Self {
+        self.tx.callvalue = Some(callvalue);
+        self
+    }
+
+    pub fn set_origin(mut self, origin: Address) -> Self {
+        self.tx.origin = Some(origin);
+        self
+    }
+
+    pub fn set_gasprice(mut self, gasprice: BitVec<32>) -> Self {
+        self.tx.gasprice = Some(gasprice);
+        self
+    }
+
+    pub fn set_base_fee(mut self, base_fee: BitVec<32>) -> Self {
+        self.block.base_fee = Some(base_fee);
+        self
+    }
+
+    pub fn set_chain_id(mut self, chain_id: BitVec<32>) -> Self {
+        self.block.chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn set_coinbase(mut self, coinbase: Address) -> Self {
+        self.block.coinbase = Some(coinbase);
+        self
+    }
+
+    pub fn set_difficulty(mut self, difficulty: BitVec<32>) -> Self {
+        self.block.difficulty = Some(difficulty);
+        self
+    }
+
+    pub fn set_gaslimit(mut self, gaslimit: BitVec<32>) -> Self {
+        self.block.gaslimit = Some(gaslimit);
+        self
+    }
+
+    pub fn set_number(mut self, number: BitVec<32>) -> Self {
+        self.block.number = Some(number);
+        self
+    }
+
+    pub fn set_timestamp(mut self, timestamp: BitVec<32>) -> Self {
+        self.block.timestamp = Some(timestamp);
+        self
+    }
+
+    // The transaction at position `index` of a sequence. Its caller, calldata, callvalue, origin
+    // and gas price are the named symbolic values of that transaction; the block, contract code
+    // and balances are kept.
+    pub fn for_transaction(mut self, index: usize) -> Self {
+        self.tx = TransactionContext {
+            index,
+            ..Default::default()
+        };
+        self
+    }
+
+    // Like `for_transaction`, but keeps the configured transaction fields
+    pub fn set_transaction_index(mut self, index: usize) -> Self {
+        self.tx.index = index;
+        self
+    }
+
+    pub fn transaction_index(&self) -> usize {
+        self.tx.index
+    }
+
+    pub fn set_contract_code(mut self, addr: Address, pgm: Program) -> Self {
+        self.code.insert(addr, pgm);
+        self
+    }
+
+    // Each of the getters below returns the configured value, or the named symbolic value of
+    // the field when none is configured. Addresses are zero-extended to a stack word.
     pub fn caller(&self) -> BitVec<32> {
-        if let Some(ref caller) = self.tx.caller {
-            caller.as_ref().clone().into()
-        } else {
-            caller().apply(&[]).as_bv().unwrap().into()
-        }
+        address_or(self.tx.caller.as_ref(), caller(self.tx.index))
     }
-    
+
+    pub fn origin(&self) -> BitVec<32> {
+        address_or(self.tx.origin.as_ref(), origin(self.tx.index))
+    }
+
+    pub fn callvalue(&self) -> BitVec<32> {
+        value_or(self.tx.callvalue.as_ref(), call_value(self.tx.index))
+    }
+
+    pub fn gasprice(&self) -> BitVec<32> {
+        value_or(self.tx.gasprice.as_ref(), gas_price(self.tx.index))
+    }
+
+    pub fn base_fee(&self) -> BitVec<32> {
+        value_or(self.block.base_fee.as_ref(), base_fee())
+    }
+
+    pub fn chain_id(&self) -> BitVec<32> {
+        value_or(self.block.chain_id.as_ref(), chain_id())
+    }
+
+    pub fn coinbase(&self) -> BitVec<32> {
+        address_or(self.block.coinbase.as_ref(), coinbase())
+    }
+
+    pub fn difficulty(&self) -> BitVec<32> {
+        value_or(self.block.difficulty.as_ref(), difficulty())
+    }
+
+    pub fn gaslimit(&self) -> BitVec<32> {
+        value_or(self.block.gaslimit.as_ref(), gas_lim())
+    }
+
+    pub fn number(&self) -> BitVec<32> {
+        value_or(self.block.number.as_ref(), block_num())
+    }
+
+    pub fn timestamp(&self) -> BitVec<32> {
+        value_or(self.block.timestamp.as_ref(), timestamp())
+    }
+
+    // Hash of block `number`. Only the 256 most recent blocks have one; the hash of any
+    // other block, including the current one, is zero.
+    pub fn block_hash(&self, number: &BitVec<32>) -> BitVec<32> {
+        let ctx = ctx();
+        let current = self.number();
+        let (number, current) = (number.as_ref(), current.as_ref());
+        let recent = Bool::and(
+            ctx,
+            &[
+                &number.bvult(current),
+                &current.bvsub(number).bvule(&BV::from_u64(ctx, 256, 256)),
+            ],
+        );
+        let hash = block_hash().apply(&[number]).as_bv().unwrap();
+        recent
+            .ite(&hash, &BV::from_u64(ctx, 0, 256))
+            .simplify()
+            .into()
+    }
+
+    // Balances of every account when the transaction starts: the ones configured with
+    // `set_balance`, and an unknown balance for every other account
+    pub fn initial_balances(&self) -> Balances {
+        self.balances
+            .iter()
+            .fold(Balances::default(), |balances, (addr, bal)| {
+                balances.with_balance(addr, &(*bal).into())
+            })
+    }
+
     pub fn get_contract_code(&self, addr: &Address) -> Option<&Program> {
         self.code.get(addr)
     }
 
+    // Calldata given by the environment; None if it is symbolic
+    pub fn calldata(&self) -> Option<&[BitVec<1>]> {
+        self.tx.calldata.as_deref()
+    }
+
     pub fn calldatasize(&self) -> BitVec<32> {
         if let Some(ref cd) = self.tx.calldata {
             bvi(cd.len() as i32)
         } else {
-            let call_data_sz = call_data_size().apply(&[]).as_bv().unwrap();
+            let call_data_sz = call_data_size(self.tx.index).apply(&[]).as_bv().unwrap();
             call_data_sz.into()
         }
     }
 
     pub fn calldataload(&self, offset: &BitVec<32>) -> BitVec<32> {
-        
-        if let Some(ref cd) = self.tx.calldata {
-            let offset = offset.clone().into();
-            let bv_arr = cd[offset..].to_vec();
-            let bv = bitvec_array_to_bv(bv_arr);
-            let bv = BitVec::from(bv);
-            bv
-        } else {
-            call_data_load().apply(&[offset.as_ref()]).as_bv().unwrap().into()
+        (0..32)
+            .map(|i| {
+                let i: BitVec<32> = bvi(i);
+                let idx: BitVec<32> = offset.as_ref().bvadd(i.as_ref()).into();
+                self.calldata_byte(&idx).as_ref().clone()
+            })
+            .reduce(|word, byte| word.concat(&byte))
+            .unwrap()
+            .simplify()
+            .into()
+    }
+
+    // Byte `idx` of the calldata; zero past its end
+    pub fn calldata_byte(&self, idx: &BitVec<32>) -> BitVec<1> {
+        let ctx = ctx();
+        let idx = idx.as_ref().simplify();
+        match (self.tx.calldata.as_ref(), idx.as_u64()) {
+            (Some(cd), Some(i)) => cd.get(i as usize).cloned().unwrap_or_default(),
+            // A symbolic offset into concrete calldata selects among its bytes
+            (Some(cd), None) => cd
+                .iter()
+                .enumerate()
+                .rev()
+                .fold(BV::from_u64(ctx, 0, 8), |rest, (i, byte)| {
+                    idx._eq(&BV::from_u64(ctx, i as u64, 256))
+                        .ite(byte.as_ref(), &rest)
+                })
+                .into(),
+            (None, _) => call_data_byte(self.tx.index, &idx).into(),
         }
     }
 
 }
+fn value_or(value: Option<&BitVec<32>>, symbolic: FuncDecl<'static>) -> BitVec<32> {
+    match value {
+        Some(value) => value.clone(),
+        None => symbolic.apply(&[]).as_bv().unwrap().into(),
+    }
+}
+
+fn address_or(addr: Option<&Address>, symbolic: FuncDecl<'static>) -> BitVec<32> {
+    match addr {
+        Some(addr) => addr.as_ref().zero_ext(96).into(),
+        None => symbolic.apply(&[]).as_bv().unwrap().into(),
+    }
+}
+
 // Note: tradeoffs between Log with LogTopic enum vs top-lvl enum for Log vs merely a struct
 // with a [Option<BitVec<32>>; 4] array for topics...
 #[derive(Debug, Clone)]
 pub struct Log {
-    data: Vec<BitVec<1>>,
-    topics: LogTopic
+    pub data: Vec<BitVec<1>>,
+    pub topics: LogTopic
 }
 
 #[derive(Debug, Clone)]
 pub enum LogTopic{
+    Zero,
     One(BitVec<32>),
     Two(BitVec<32>, BitVec<32>),
     Three(BitVec<32>, BitVec<32>, BitVec<32>),
     Four(BitVec<32>, BitVec<32>, BitVec<32>, BitVec<32>),
 }
 
+impl LogTopic {
+    // Topics in the order they were popped by LOG0..LOG4
+    pub fn from_slice(topics: &[BitVec<32>]) -> Self {
+        match topics {
+            [] => Self::Zero,
+            [a] => Self::One(a.clone()),
+            [a, b] => Self::Two(a.clone(), b.clone()),
+            [a, b, c] => Self::Three(a.clone(), b.clone(), c.clone()),
+            [a, b, c, d] => Self::Four(a.clone(), b.clone(), c.clone(), d.clone()),
+            _ => panic!("A log has at most four topics, got {}", topics.len()),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<BitVec<32>> {
+        match self {
+            Self::Zero => vec![],
+            Self::One(a) => vec![a.clone()],
+            Self::Two(a, b) => vec![a.clone(), b.clone()],
+            Self::Three(a, b, c) => vec![a.clone(), b.clone(), c.clone()],
+            Self::Four(a, b, c, d) => vec![a.clone(), b.clone(), c.clone(), d.clone()],
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EvmResult {
     Failed {
         msg: String
     },
+    // Halted with RETURN
     Success {
-        ret_val: BitVec<32>
+        ret_val: Vec<BitVec<1>>
+    },
+    // Halted with REVERT
+    Revert {
+        data: Vec<BitVec<1>>
+    },
+    // Halted with STOP or by running off the end of the code
+    Stop,
+    Invalid,
+    // Jumped to a destination that is not a JUMPDEST
+    InvalidJump,
+    // Executed an instruction with fewer stack items than it pops
+    StackUnderflow,
+    // Executed an instruction that would grow the stack past 1024 items
+    StackOverflow,
+    // Executed an instruction costing more gas than was left
+    OutOfGas,
+    // Executed an instruction that changes the world inside a STATICCALL
+    StaticStateChange,
+    // Not halted; exploration of the path was stopped by its budget
+    Incomplete {
+        reason: BudgetExceeded
+    },
+}
+
+impl EvmResult {
+    pub fn is_revert(&self) -> bool {
+        matches!(self, Self::Revert { .. })
+    }
+
+    // Whether the frame's state changes are kept
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Success { .. } | Self::Stop)
+    }
+
+    // Whether the frame halted in a way that uses up all of its gas
+    pub fn is_exceptional(&self) -> bool {
+        matches!(
+            self,
+            Self::Invalid
+                | Self::InvalidJump
+                | Self::StackUnderflow
+                | Self::StackOverflow
+                | Self::OutOfGas
+                | Self::StaticStateChange
+        )
+    }
+
+    // Decoded payload of a REVERT; None for every other halt
+    pub fn revert_reason(&self) -> Option<RevertReason> {
+        match self {
+            Self::Revert { data } => Some(RevertReason::decode(data)),
+            _ => None,
+        }
+    }
+
+    // Bytes returned by RETURN or REVERT; empty for every other halt
+    pub fn output(&self) -> &[BitVec<1>] {
+        match self {
+            Self::Success { ret_val } => ret_val,
+            Self::Revert { data } => data,
+            _ => &[],
+        }
+    }
+}
+
+impl From<InstructionError> for EvmResult {
+    fn from(err: InstructionError) -> Self {
+        match err {
+            InstructionError::StackUnderflow { .. } => Self::StackUnderflow,
+            InstructionError::StackOverflow { .. } => Self::StackOverflow,
+            InstructionError::StaticStateChange { .. } => Self::StaticStateChange,
+            InstructionError::Unsupported { pc } => Self::Incomplete {
+                reason: BudgetExceeded::Unsupported { pc },
+            },
+        }
     }
 }
 
@@ -118,9 +412,13 @@ impl Default for EvmResult {
 }
 #[derive(Debug, Clone, Default)]
 pub struct TransactionContext {
+    // Position of the transaction in a sequence, which names its symbolic fields
+    index: usize,
     calldata: Option<Vec<BitVec<1>>>,
     caller: Option<Address>,
     callvalue: Option<BitVec<32>>,
+    origin: Option<Address>,
+    gasprice: Option<BitVec<32>>,
 }
 
 #[derive(Debug, Clone, Default)]
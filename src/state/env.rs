@@ -34,62 +34,72 @@ pub fn call_data_load<'ctx>() -> FuncDecl<'ctx> {
     )
 }
 
-pub fn sha3<'ctx>(size: u32) -> FuncDecl<'ctx> {
-    let id = uuid::Uuid::new_v4();
-    let func = FuncDecl::new(
-        ctx(),
-        format!("sha3_{}", id).as_str(),
-        &[&Sort::bitvector(ctx(), size)],
-        &Sort::bitvector(ctx(), 256),
-    );
+// Name of a symbol belonging to transaction `tx` of a sequence. The first transaction keeps
+// the plain name, so that a single transaction reads as it always has.
+fn tx_symbol(name: &str, tx: usize) -> String {
+    match tx {
+        0 => name.to_string(),
+        _ => format!("{}_{}", name, tx),
+    }
+}
+
+// Symbolic calldata of transaction `tx` as a byte array indexed by offset. Modeling bytes
+// rather than words lets a model be read back as concrete calldata.
+pub fn call_data<'ctx>(tx: usize) -> Array<'ctx> {
+    let ctx = ctx();
+    Array::new_const(
+        ctx,
+        tx_symbol("calldata", tx),
+        &Sort::bitvector(ctx, 256),
+        &Sort::bitvector(ctx, 8),
+    )
+}
 
-    eprintln!("SHA3 FUNC: {:#?}", func);
-    func
+// Byte `idx` of the symbolic calldata of transaction `tx`; zero past calldatasize
+pub fn call_data_byte<'ctx>(tx: usize, idx: &BV<'ctx>) -> BV<'ctx> {
+    let ctx = ctx();
+    let size = call_data_size(tx).apply(&[]).as_bv().unwrap();
+    let byte = call_data(tx).select(idx).as_bv().unwrap();
+    idx.bvult(&size).ite(&byte, &BV::from_u64(ctx, 0, 8))
 }
 
-pub fn call_value<'ctx>() -> FuncDecl<'ctx> {
+pub fn call_value<'ctx>(tx: usize) -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(ctx, "callvalue", &[], &Sort::bitvector(ctx, 256))
+    FuncDecl::new(ctx, tx_symbol("callvalue", tx), &[], &Sort::bitvector(ctx, 256))
 }
 
-pub fn call_data_size<'ctx>() -> FuncDecl<'ctx> {
+pub fn call_data_size<'ctx>(tx: usize) -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(ctx, "calldatasize", &[], &Sort::bitvector(ctx, 256))
+    FuncDecl::new(ctx, tx_symbol("calldatasize", tx), &[], &Sort::bitvector(ctx, 256))
 }
 
-pub fn caller<'ctx>() -> FuncDecl<'ctx> {
+pub fn caller<'ctx>(tx: usize) -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(ctx, "caller", &[], &Sort::bitvector(ctx, 256))
+    FuncDecl::new(ctx, tx_symbol("caller", tx), &[], &Sort::bitvector(ctx, 256))
 }
 
-pub fn origin<'ctx>() -> FuncDecl<'ctx> {
+pub fn origin<'ctx>(tx: usize) -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(ctx, "origin", &[], &Sort::bitvector(ctx, 256))
+    FuncDecl::new(ctx, tx_symbol("origin", tx), &[], &Sort::bitvector(ctx, 256))
 }
 
 pub fn address() -> BitVec<20> {
     random_bv_arg()
 }
 
-// Takes random bitvec as argument so that gas is not treated as a constant function.
-pub fn gas<'ctx>() -> FuncDecl<'ctx> {
+pub fn gas_lim<'ctx>() -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(
-        ctx,
-        "gas",
-        &[&Sort::bitvector(ctx, 256)],
-        &Sort::bitvector(ctx, 256),
-    )
+    FuncDecl::new(ctx, "gaslimit", &[], &Sort::bitvector(ctx, 256))
 }
 
-pub fn gas_lim<'ctx>() -> FuncDecl<'ctx> {
+pub fn gas_price<'ctx>(tx: usize) -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(ctx, "gaslimit", &[], &Sort::bitvector(ctx, 256))
+    FuncDecl::new(ctx, tx_symbol("gasprice", tx), &[], &Sort::bitvector(ctx, 256))
 }
 
-pub fn gas_price<'ctx>() -> FuncDecl<'ctx> {
+pub fn base_fee<'ctx>() -> FuncDecl<'ctx> {
     let ctx = ctx();
-    FuncDecl::new(ctx, "extcodesize", &[], &Sort::bitvector(ctx, 256))
+    FuncDecl::new(ctx, "basefee", &[], &Sort::bitvector(ctx, 256))
 }
 
 pub fn coinbase<'ctx>() -> FuncDecl<'ctx> {
@@ -117,6 +127,17 @@ pub fn ext_code_size<'ctx>() -> FuncDecl<'ctx> {
     )
 }
 
+// Byte `idx` of the code of an account whose code is unknown
+pub fn ext_code_byte<'ctx>() -> FuncDecl<'ctx> {
+    let ctx = ctx();
+    FuncDecl::new(
+        ctx,
+        "extcode",
+        &[&Sort::bitvector(ctx, 256), &Sort::bitvector(ctx, 256)],
+        &Sort::bitvector(ctx, 8),
+    )
+}
+
 pub fn block_num<'ctx>() -> FuncDecl<'ctx> {
     let ctx = ctx();
     FuncDecl::new(ctx, "blocknumber", &[], &Sort::bitvector(ctx, 256))
@@ -140,15 +161,3 @@ pub fn ext_code_hash<'ctx>() -> FuncDecl<'ctx> {
         &Sort::bitvector(ctx, 256),
     )
 }
-
-// We add an extra argument here because the balance of an address is not necessarily the same during
-// every step of a contract's execution.
-pub fn balance<'ctx>() -> FuncDecl<'ctx> {
-    let ctx = ctx();
-    FuncDecl::new(
-        ctx,
-        "balance",
-        &[&Sort::bitvector(ctx, 256), &Sort::bitvector(ctx, 256)],
-        &Sort::bitvector(ctx, 256),
-    )
-}
@@ -1,10 +1,15 @@
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::RwLock;
 
+use crate::gas::{self, GasMeter};
+use crate::keccak::Hashed;
 use crate::machine::ExecBranch;
 use crate::parser::Program;
+use crate::smt::{ctx, BitVec};
 use crate::state::tree::NodeId;
-use crate::storage::{AccountStorage, Address};
-use crate::traits::MachineState;
+use crate::storage::{AccountStorage, Address, GlobalStorage};
+use crate::traits::{Invertible, MachineState};
 use crate::{
     instruction::Instruction,
     memory::Memory,
@@ -12,26 +17,105 @@ use crate::{
     stack::Stack,
     traits::{MachineComponent, MachineInstruction},
 };
-use z3_ext::ast::Bool;
+use z3_ext::ast::{Ast, Bool};
 
-use super::context::ExecutionEnv;
+use super::context::{EvmResult, ExecutionEnv, Log};
 
 #[derive(Clone, Default)]
 pub struct EvmState {
     pub memory: Memory,
-    pub storage: AccountStorage,
     pub stack: Stack<32>,
     pc: usize,
     pub pgm: Program,
     pub address: Address,
     pub halt: bool,
+    // Output of the most recent message call, as read by RETURNDATASIZE and RETURNDATACOPY
+    pub return_data: ReturnData,
+    pub calls: Vec<CallChange>,
+    // Number of frames above this one; zero for the transaction's entry frame
+    pub depth: usize,
+    // Set in frames entered through STATICCALL and every frame below them, which may not
+    // change the world
+    pub is_static: bool,
+    // Events emitted along the path leading to this state, in order
+    pub logs: Vec<Log>,
+    // Set once the state halts
+    pub result: Option<EvmResult>,
+    // Inverses of every record applied to this state, most recent last
+    pub history: History,
+    // Gas used along the path leading to this state
+    pub gas: GasMeter,
+    // Position of the transaction in a sequence; zero outside of one
+    pub transaction: usize,
+    // Storage, code, nonce and balance of every account along the path leading to this state
+    pub world: GlobalStorage,
+    // Hashes taken along the path leading to this state, including by earlier transactions
+    // and nested frames. Their axioms hold alongside its path condition.
+    pub hashes: Vec<Hashed>,
+    // Calldata of the transaction when the environment gives it; None if it is symbolic
+    pub calldata: Option<Vec<BitVec<1>>>,
 }
 
+// Inverses of the records applied to a state. The states of a path share the inverses they
+// have in common, so cloning a state doesn't copy its history.
+#[derive(Clone, Default)]
+pub struct History {
+    last: Option<Rc<HistoryEntry>>,
+    len: usize,
+}
+
+#[derive(Clone)]
+struct HistoryEntry {
+    inverse: InverseRecord,
+    prev: Option<Rc<HistoryEntry>>,
+}
+
+impl History {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, inverse: InverseRecord) {
+        let prev = self.last.take();
+        self.last = Some(Rc::new(HistoryEntry { inverse, prev }));
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<InverseRecord> {
+        let last = self.last.take()?;
+        self.len -= 1;
+        let entry = Rc::try_unwrap(last).unwrap_or_else(|shared| (*shared).clone());
+        self.last = entry.prev;
+        Some(entry.inverse)
+    }
+
+    // The most recent inverse, copied out of the entries shared with other states first
+    fn last_mut(&mut self) -> Option<&mut InverseRecord> {
+        self.last.as_mut().map(|last| &mut Rc::make_mut(last).inverse)
+    }
+}
+
+impl Drop for History {
+    // Frees the entries no other state shares one at a time, so that a long history doesn't
+    // overflow the stack by being dropped recursively
+    fn drop(&mut self) {
+        let mut next = self.last.take();
+        while let Some(entry) = next {
+            next = Rc::try_unwrap(entry).ok().and_then(|mut entry| entry.prev.take());
+        }
+    }
+}
 
 impl MachineComponent for EvmState {
     type Record = MachineRecord<32>;
 
     fn apply_change(&mut self, rec: Self::Record) {
+        let inverse = self.inverse_of(&rec);
+        let charge = gas::charge(self, &rec);
         let MachineRecord {
             halt,
             pc,
@@ -39,6 +123,10 @@ impl MachineComponent for EvmState {
             mem,
             constraints,
             storage,
+            call,
+            create,
+            log,
+            hashes,
         } = rec;
         if let Some(mem) = mem {
             self.memory.apply_change(mem);
@@ -46,19 +134,211 @@ impl MachineComponent for EvmState {
         if let Some(stack) = stack {
             self.stack.apply_change(stack);
         }
+        if let Some(storage) = storage {
+            self.world.apply_change(storage);
+        }
+        if let Some(call) = call {
+            // CALLCODE sends value to the calling account itself, so only CALL moves any
+            if let (CallKind::Call, Some(value)) = (&call.kind, &call.value) {
+                self.world
+                    .balances
+                    .transfer(&self.address, &call.target, value, &call.success);
+            }
+            self.return_data = call.return_data.clone();
+            self.calls.push(call);
+        }
+        if let Some(create) = create {
+            let nonce = self.world.nonce(&self.address);
+            self.world.set_nonce(&self.address, nonce + 1);
+            // The address is derived from the creator's nonce, so it holds no prior account. A
+            // failed creation leaves none behind either; one that may have failed leaves the
+            // account unknown.
+            if create.success.as_bool() == Some(true) {
+                self.world.create_account(&create.address, create.deployed);
+            }
+            self.world.balances.transfer(
+                &self.address,
+                &create.address,
+                &create.value,
+                &create.success,
+            );
+            self.return_data = create.return_data.clone();
+        }
+        if let Some(log) = log {
+            self.logs.push(log);
+        }
+        self.hashes.extend(hashes);
+        self.gas.charge(charge);
+        if halt {
+            let result = self.halt_result(pc.0);
+            if result.is_exceptional() {
+                self.gas.consume_all();
+            }
+            self.result = Some(result);
+        }
         self.halt = halt;
         self.set_pc(pc.1);
+        self.history.push(inverse);
     }
 }
 
 impl<'ctx> EvmState {
     pub fn with_pgm(pgm: Program) -> Self {
+        // The contract is already deployed, so the world has no record of its account: its
+        // storage may hold anything
         Self {
             pgm,
             ..Default::default()
         }
     }
 
+    // Starts the transaction described by `env`: the callvalue moves from the caller to this
+    // account. Returns the condition that the caller can afford the callvalue, which holds on
+    // every path of the transaction.
+    pub fn begin_transaction(&mut self, env: &ExecutionEnv) -> Bool<'static> {
+        self.calldata = env.calldata().map(|calldata| calldata.to_vec());
+        let caller: Address = env.caller().as_ref().extract(159, 0).simplify().into();
+        let value = env.callvalue();
+        let can_pay = self.world.balances.can_afford(&caller, &value);
+        self.world
+            .balances
+            .transfer(&caller, &self.address, &value, &Bool::from_bool(ctx(), true));
+        can_pay
+    }
+
+    fn inverse_of(&self, rec: &MachineRecord<32>) -> InverseRecord {
+        InverseRecord {
+            stack: rec.stack.as_ref().map(|stack| self.stack.inverse_of(stack)),
+            mem: rec.mem.as_ref().map(|mem| self.memory.inverse_of(mem)),
+            storage: rec
+                .storage
+                .as_ref()
+                .map(|storage| self.world.inverse_of(storage)),
+            pc: self.pc,
+            halt: self.halt,
+            result: self.result.clone(),
+            return_data: (rec.call.is_some() || rec.create.is_some())
+                .then(|| self.return_data.clone()),
+            nonce: self.world.nonce(&self.address),
+            calls_len: self.calls.len(),
+            logs_len: self.logs.len(),
+            transfers_len: self.world.balances.transfers().len(),
+            hashes_len: self.hashes.len(),
+            gas: self.gas.checkpoint(),
+            created: rec.create.as_ref().map(|create| {
+                let prev = self.world.account(&create.address).cloned();
+                (create.address.clone(), prev)
+            }),
+            world: None,
+        }
+    }
+
+    // Halts without applying the instruction at the current pc, e.g. on a stack underflow or
+    // on operands that can't be modelled
+    pub fn halt_exceptionally(&mut self, result: EvmResult) {
+        let rec = MachineRecord {
+            stack: None,
+            mem: None,
+            storage: None,
+            pc: (self.pc, self.pc),
+            constraints: None,
+            halt: true,
+            ..Default::default()
+        };
+        // Recorded so the halt can be undone like any other step
+        self.history.push(self.inverse_of(&rec));
+        if result.is_exceptional() {
+            self.gas.consume_all();
+        }
+        self.halt = true;
+        self.result = Some(result);
+    }
+
+    // Reverts the most recently applied record. Returns false if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let inverse = match self.history.pop() {
+            Some(inverse) => inverse,
+            None => return false,
+        };
+        let InverseRecord {
+            stack,
+            mem,
+            storage,
+            pc,
+            halt,
+            result,
+            return_data,
+            nonce,
+            calls_len,
+            logs_len,
+            transfers_len,
+            hashes_len,
+            gas,
+            created,
+            world,
+        } = inverse;
+        // The world a nested frame ended in is undone before the record that entered it
+        if let Some(world) = world {
+            self.world = world;
+        }
+        if let Some(stack) = stack {
+            self.stack.apply_change(stack);
+        }
+        if let Some(mem) = mem {
+            self.memory.apply_change(mem);
+        }
+        if let Some(storage) = storage {
+            self.world.apply_change(storage);
+        }
+        if let Some((address, prev)) = created {
+            self.world.restore_account(&address, prev);
+        }
+        self.calls.truncate(calls_len);
+        self.logs.truncate(logs_len);
+        self.world.balances.truncate(transfers_len);
+        self.hashes.truncate(hashes_len);
+        self.world.set_nonce(&self.address, nonce);
+        if let Some(return_data) = return_data {
+            self.return_data = return_data;
+        }
+        self.gas.restore(gas);
+        self.result = result;
+        self.halt = halt;
+        self.pc = pc;
+        true
+    }
+
+    // The start of the transaction after the one this state ended: a fresh frame running the
+    // code of this state's account against the world as this state left it. None if the
+    // account has no code.
+    pub fn next_transaction(&self) -> Option<EvmState> {
+        let code = self.world.code(&self.address)?.clone();
+        if code.bytes.is_empty() {
+            return None;
+        }
+        let mut next = EvmState::with_pgm(code);
+        next.address = self.address.clone();
+        next.world = self.world.clone();
+        next.world.commit();
+        next.transaction = self.transaction + 1;
+        next.hashes = self.hashes.clone();
+        Some(next)
+    }
+
+    // Replaces the world with the one a nested frame entered by the most recent record ended
+    // in. Undoing that record restores the world from before it.
+    pub(crate) fn adopt_world(&mut self, world: GlobalStorage) {
+        let prev = std::mem::replace(&mut self.world, world);
+        if let Some(inverse) = self.history.last_mut() {
+            inverse.world.get_or_insert(prev);
+        }
+    }
+
+    // Undoes up to `steps` records and returns how many were undone
+    pub fn rewind(&mut self, steps: usize) -> usize {
+        (0..steps).take_while(|_| self.undo()).count()
+    }
+
     pub fn pgm_counter(&self) -> usize {
         self.pc
     }
@@ -71,6 +351,9 @@ impl<'ctx> EvmState {
             //     self.pgm.get_size()
             // );
             self.halt = true;
+            if self.result.is_none() {
+                self.result = Some(EvmResult::Stop);
+            }
         }
     }
 
@@ -87,6 +370,32 @@ impl<'ctx> EvmState {
             .get(self.pc)
             .expect(&format!("Expected instruction at pc: {}", self.pc))
     }
+    // Outcome of halting at `pc`. RETURN and REVERT leave their offset and size arguments on
+    // the stack, since their records do not pop them.
+    fn halt_result(&self, pc: usize) -> EvmResult {
+        match self.pgm.get(pc) {
+            Some(Instruction::Return) => EvmResult::Success {
+                ret_val: self.halt_output(),
+            },
+            Some(Instruction::Revert) => EvmResult::Revert {
+                data: self.halt_output(),
+            },
+            Some(Instruction::Invalid) => EvmResult::Invalid,
+            // A jump only halts when its destination is invalid
+            Some(Instruction::Jump | Instruction::JumpI) => EvmResult::InvalidJump,
+            _ => EvmResult::Stop,
+        }
+    }
+
+    fn halt_output(&self) -> Vec<BitVec<1>> {
+        let [offset, size] = self.stack().peek_top().unwrap();
+        let mut offset = offset.clone();
+        let mut size = size.clone();
+        offset.simplify();
+        size.simplify();
+        self.mem().read_bytes(offset, size)
+    }
+
     pub fn curr_inst_debug(&self) -> Instruction {
         if !self.can_continue() {
             eprintln!("Curr instruction debug requested but cannot continue");
@@ -1,4 +1,5 @@
 pub mod context;
 pub mod env;
 pub mod evm;
+pub mod revert;
 pub mod tree;
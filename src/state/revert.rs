@@ -0,0 +1,153 @@
+use crate::conversion::concrete_byte_vec;
+use crate::smt::BitVec;
+
+// Selectors of the revert payloads emitted by solc
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+// Classification of a REVERT payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    // revert() or require(cond) without a message
+    Empty,
+    // Panic(uint256); None when the code is symbolic
+    Panic(Option<PanicCode>),
+    // Error(string); None when the message is symbolic or malformed
+    Error(Option<String>),
+    // A custom error with the given selector
+    Custom([u8; 4]),
+    // Payload whose selector is symbolic, or which is too short to hold one
+    Unknown,
+}
+
+// Panic codes as defined by solc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicCode {
+    Generic,
+    Assert,
+    ArithmeticOverflow,
+    DivisionByZero,
+    EnumConversion,
+    StorageEncoding,
+    EmptyArrayPop,
+    ArrayOutOfBounds,
+    OutOfMemory,
+    UninitializedFunction,
+    Other(u64),
+}
+
+impl From<u64> for PanicCode {
+    fn from(code: u64) -> Self {
+        match code {
+            0x00 => Self::Generic,
+            0x01 => Self::Assert,
+            0x11 => Self::ArithmeticOverflow,
+            0x12 => Self::DivisionByZero,
+            0x21 => Self::EnumConversion,
+            0x22 => Self::StorageEncoding,
+            0x31 => Self::EmptyArrayPop,
+            0x32 => Self::ArrayOutOfBounds,
+            0x41 => Self::OutOfMemory,
+            0x51 => Self::UninitializedFunction,
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl RevertReason {
+    pub fn decode(data: &[BitVec<1>]) -> Self {
+        if data.is_empty() {
+            return Self::Empty;
+        }
+        if data.len() < 4 {
+            return Self::Unknown;
+        }
+        let selector = match concrete_byte_vec(&data[..4]) {
+            Some(selector) => [selector[0], selector[1], selector[2], selector[3]],
+            None => return Self::Unknown,
+        };
+        let args = &data[4..];
+        match selector {
+            PANIC_SELECTOR => Self::Panic(
+                args.get(..32)
+                    .and_then(concrete_byte_vec)
+                    .and_then(|code| word_to_u64(&code))
+                    .map(PanicCode::from),
+            ),
+            ERROR_SELECTOR => {
+                Self::Error(concrete_byte_vec(args).and_then(|args| decode_string(&args)))
+            }
+            selector => Self::Custom(selector),
+        }
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, Self::Panic(_))
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::Error(_))
+    }
+}
+
+// The value of a big-endian 32 byte word, if it fits in a u64
+fn word_to_u64(word: &[u8]) -> Option<u64> {
+    let (high, low) = word.split_at(word.len().checked_sub(8)?);
+    if high.iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(u64::from_be_bytes(low.try_into().ok()?))
+}
+
+// ABI decoding of a single string argument: offset, then length, then the bytes
+fn decode_string(args: &[u8]) -> Option<String> {
+    let offset = word_to_u64(args.get(..32)?)? as usize;
+    let len_word = args.get(offset..offset.checked_add(32)?)?;
+    let len = word_to_u64(len_word)? as usize;
+    let start = offset + 32;
+    let bytes = args.get(start..start.checked_add(len)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+#[cfg(test)]
+fn to_bitvecs(bytes: &[u8]) -> Vec<BitVec<1>> {
+    bytes.iter().map(|b| BitVec::from([*b])).collect()
+}
+
+#[test]
+fn test_decode_panic() {
+    let mut data = PANIC_SELECTOR.to_vec();
+    data.extend_from_slice(&[0u8; 31]);
+    data.push(0x11);
+    assert_eq!(
+        RevertReason::Panic(Some(PanicCode::ArithmeticOverflow)),
+        RevertReason::decode(&to_bitvecs(&data))
+    );
+}
+
+#[test]
+fn test_decode_error_string() {
+    // Error("Not enough funds")
+    let data = hex::decode(
+        "08c379a0\
+         0000000000000000000000000000000000000000000000000000000000000020\
+         0000000000000000000000000000000000000000000000000000000000000010\
+         4e6f7420656e6f7567682066756e647300000000000000000000000000000000",
+    )
+    .unwrap();
+    assert_eq!(
+        RevertReason::Error(Some("Not enough funds".to_string())),
+        RevertReason::decode(&to_bitvecs(&data))
+    );
+}
+
+#[test]
+fn test_decode_custom_and_empty() {
+    assert_eq!(RevertReason::Empty, RevertReason::decode(&[]));
+    assert_eq!(
+        RevertReason::Custom([0xde, 0xad, 0xbe, 0xef]),
+        RevertReason::decode(&to_bitvecs(&[0xde, 0xad, 0xbe, 0xef]))
+    );
+    let symbolic = vec![BitVec::new_const("selector_byte"); 4];
+    assert_eq!(RevertReason::Unknown, RevertReason::decode(&symbolic));
+}
@@ -1,4 +1,5 @@
 use super::evm::*;
+use crate::machine::ExecBranch;
 use crate::{ctx, instruction::Instruction, z3_ext::ast::Ast, z3_ext::ast::Bool};
 use std::borrow::BorrowMut;
 use uuid::Uuid;
@@ -48,6 +49,9 @@ pub struct StateTree<'ctx> {
     pub(crate) path_condition: Option<Bool<'ctx>>,
     pub(crate) left: Option<Box<StateTree<'ctx>>>,
     pub(crate) right: Option<Box<StateTree<'ctx>>>,
+    // Successors of instructions with more than two outcomes (e.g. a nested call whose callee
+    // halts on several paths). A node has either forks or left/right children, never both.
+    pub(crate) forks: Vec<StateTree<'ctx>>,
 }
 
 impl<'ctx> From<(EvmState, Bool<'ctx>)> for StateTree<'ctx> {
@@ -58,6 +62,7 @@ impl<'ctx> From<(EvmState, Bool<'ctx>)> for StateTree<'ctx> {
             path_condition: Some(t.1),
             left: None,
             right: None,
+            forks: vec![],
         }
     }
 }
@@ -70,6 +75,7 @@ impl<'ctx> From<(EvmState, Option<Bool<'ctx>>)> for StateTree<'ctx> {
             path_condition: t.1,
             left: None,
             right: None,
+            forks: vec![],
         }
     }
 }
@@ -88,6 +94,7 @@ impl<'ctx> From<(EvmState, Vec<Bool<'ctx>>)> for StateTree<'ctx> {
             path_condition: cond,
             left: None,
             right: None,
+            forks: vec![],
         }
     }
 }
@@ -114,6 +121,9 @@ impl<'ctx> StateTree<'ctx> {
             let right_tree_inorder = right.inorder();
             items.extend(right_tree_inorder);
         }
+        self.forks
+            .iter()
+            .for_each(|fork| items.extend(fork.inorder()));
         items
     }
 
@@ -123,22 +133,66 @@ impl<'ctx> StateTree<'ctx> {
         all_paths: &mut Vec<Vec<(NodeId, Instruction, Option<Bool<'ctx>>)>>,
     ) {
         if let Some(ref node) = *node {
-            current_path.push((
-                node.id.clone(),
-                node.val.curr_instruction(),
-                node.path_condition.clone(),
-            ));
-
-            if node.left.is_none() && node.right.is_none() {
-                all_paths.push(current_path.clone());
-            } else {
-                Self::find_paths(&node.left, current_path, all_paths);
-                Self::find_paths(&node.right, current_path, all_paths);
-            }
+            Self::find_paths_from(node, current_path, all_paths);
+        }
+    }
 
-            current_path.pop();
+    fn find_paths_from(
+        node: &StateTree<'ctx>,
+        current_path: &mut Vec<(NodeId, Instruction, Option<Bool<'ctx>>)>,
+        all_paths: &mut Vec<Vec<(NodeId, Instruction, Option<Bool<'ctx>>)>>,
+    ) {
+        current_path.push((
+            node.id.clone(),
+            node.val.curr_instruction(),
+            node.path_condition.clone(),
+        ));
+
+        if node.is_leaf() {
+            all_paths.push(current_path.clone());
+        } else {
+            Self::find_paths(&node.left, current_path, all_paths);
+            Self::find_paths(&node.right, current_path, all_paths);
+            node.forks
+                .iter()
+                .for_each(|fork| Self::find_paths_from(fork, current_path, all_paths));
         }
+
+        current_path.pop();
     }
+    // Every leaf, paired with the branch conditions on the path from the root to it
+    pub fn leaf_branches(&self) -> Vec<ExecBranch<'ctx>> {
+        let mut branches = vec![];
+        self.collect_leaf_branches(&mut vec![], &mut branches);
+        branches
+    }
+
+    fn collect_leaf_branches(
+        &self,
+        conditions: &mut Vec<Bool<'ctx>>,
+        branches: &mut Vec<ExecBranch<'ctx>>,
+    ) {
+        if let Some(cond) = &self.path_condition {
+            conditions.push(cond.clone());
+        }
+        if self.is_leaf() {
+            branches.push((self.val.clone(), conditions.clone()));
+        } else {
+            if let Some(left) = &self.left {
+                left.collect_leaf_branches(conditions, branches);
+            }
+            if let Some(right) = &self.right {
+                right.collect_leaf_branches(conditions, branches);
+            }
+            self.forks
+                .iter()
+                .for_each(|fork| fork.collect_leaf_branches(conditions, branches));
+        }
+        if self.path_condition.is_some() {
+            conditions.pop();
+        }
+    }
+
     pub fn inorder_stateless(&self) -> Vec<(NodeId, Option<Bool<'ctx>>)> {
         let mut items = vec![(self.id.clone(), self.path_condition.clone())];
 
@@ -150,6 +204,9 @@ impl<'ctx> StateTree<'ctx> {
             let right_tree_inorder = right.inorder_stateless();
             items.extend(right_tree_inorder);
         }
+        self.forks
+            .iter()
+            .for_each(|fork| items.extend(fork.inorder_stateless()));
         items
     }
 
@@ -179,10 +236,27 @@ impl<'ctx> StateTree<'ctx> {
                     return Some(found_r);
                 }
             }
-            None
+            self.forks.iter().find_map(|fork| fork.find_by_id(id))
         }
     }
 
+    pub fn find_by_id_mut(&mut self, id: &NodeId) -> Option<&mut StateTree<'ctx>> {
+        let mut queue = vec![self];
+        while let Some(current) = queue.pop() {
+            if current.id == *id {
+                return Some(current);
+            }
+            if let Some(left) = current.left.as_deref_mut() {
+                queue.push(left);
+            }
+            if let Some(right) = current.right.as_deref_mut() {
+                queue.push(right);
+            }
+            queue.extend(current.forks.iter_mut());
+        }
+        None
+    }
+
     pub fn insert_left_helper(
         &mut self,
         tree: &StateTree<'ctx>,
@@ -206,11 +280,19 @@ impl<'ctx> StateTree<'ctx> {
                 return Some(res);
             }
 
-            if let Some(right) = &mut self.right {
+            let right_result = if let Some(right) = &mut self.right {
                 right.insert_left_helper(tree, id)
             } else {
                 None
+            };
+
+            if let Some(res) = right_result {
+                return Some(res);
             }
+
+            self.forks
+                .iter_mut()
+                .find_map(|fork| fork.insert_left_helper(tree, id))
         }
     }
 
@@ -236,11 +318,19 @@ impl<'ctx> StateTree<'ctx> {
                 return Some(res);
             }
 
-            if let Some(right) = &mut self.right {
-                right.insert_right_helper(tree, id)
+            let right_result = if let Some(right) = &mut self.right {
+                right.insert_right_helper(tree.clone(), id)
             } else {
                 None
+            };
+
+            if let Some(res) = right_result {
+                return Some(res);
             }
+
+            self.forks
+                .iter_mut()
+                .find_map(|fork| fork.insert_right_helper(tree.clone(), id))
         }
     }
 
@@ -258,6 +348,9 @@ impl<'ctx> StateTree<'ctx> {
             if let Some(ref mut right) = current.right {
                 queue.insert(0, right);
             }
+            for fork in current.forks.iter_mut() {
+                queue.insert(0, fork);
+            }
         }
         None
     }
@@ -275,6 +368,33 @@ impl<'ctx> StateTree<'ctx> {
             if let Some(ref mut right) = current.right {
                 queue.insert(0, right);
             }
+            for fork in current.forks.iter_mut() {
+                queue.insert(0, fork);
+            }
+        }
+        None
+    }
+
+    pub fn insert_fork_helper_iterative(
+        &mut self,
+        tree: impl Into<StateTree<'ctx>>,
+        id: Uuid,
+    ) -> Option<&StateTree> {
+        let mut queue = vec![self];
+        while let Some(current) = queue.pop() {
+            if current.id.id() == id {
+                current.forks.push(tree.into());
+                return current.forks.last();
+            }
+            if let Some(ref mut left) = current.left {
+                queue.insert(0, left);
+            }
+            if let Some(ref mut right) = current.right {
+                queue.insert(0, right);
+            }
+            for fork in current.forks.iter_mut() {
+                queue.insert(0, fork);
+            }
         }
         None
     }
@@ -305,10 +425,21 @@ impl<'ctx> StateTree<'ctx> {
         }
     }
 
+    pub fn insert_fork_of(&mut self, tree: impl Into<StateTree<'ctx>>, id: Uuid) -> NodeId {
+        match self.insert_fork_helper_iterative(tree, id) {
+            Some(i) => i.id.clone(),
+            None => panic!("Could not find id {id} in the state tree"),
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.left.is_none() && self.right.is_none() && self.forks.is_empty()
+    }
+
     pub fn leaves(&self) -> Vec<StateTree> {
         let mut leaves = vec![];
 
-        if self.left.is_none() && self.right.is_none() {
+        if self.is_leaf() {
             leaves.push((self.val.clone(), self.path_condition.clone()).into());
             return leaves;
         }
@@ -321,6 +452,10 @@ impl<'ctx> StateTree<'ctx> {
             leaves.extend(right.leaves())
         }
 
+        self.forks
+            .iter()
+            .for_each(|fork| leaves.extend(fork.leaves()));
+
         // if let Some(left) = &self.left {
 
         //     if left.left.is_none() && left.right.is_none() {
@@ -352,6 +487,7 @@ impl<'ctx> StateTree<'ctx> {
                 path_condition: Some(constraint),
                 left: None,
                 right: None,
+                forks: vec![],
             }));
         } else if let Some(left) = &mut self.left {
             left.push_branch(val, constraint)
@@ -366,6 +502,7 @@ impl<'ctx> StateTree<'ctx> {
                 path_condition: Some(constraint),
                 left: None,
                 right: None,
+                forks: vec![],
             }));
         } else if let Some(left) = &mut self.left {
             let final_constraint = if let Some(cond) = &self.path_condition {
@@ -402,6 +539,8 @@ impl<'ctx> Iterator for StateTreeIterator<'ctx> {
                 self.nexts.push(*right);
             }
 
+            self.nexts.extend(nxtt.forks);
+
             Some((nxtt.val, nxtt.path_condition))
         } else {
             None
@@ -414,7 +553,7 @@ impl<'ctx> IntoIterator for StateTree<'ctx> {
     type IntoIter = StateTreeIterator<'ctx>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let (left, right) = (self.left, self.right);
+        let (left, right, forks) = (self.left, self.right, self.forks);
         let mut iterator = StateTreeIterator {
             curr_state: Some((self.val, self.path_condition)),
             nexts: vec![],
@@ -427,6 +566,8 @@ impl<'ctx> IntoIterator for StateTree<'ctx> {
         if let Some(right) = right {
             iterator.nexts.push(*right);
         }
+
+        iterator.nexts.extend(forks);
         iterator
     }
 }
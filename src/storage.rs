@@ -1,24 +1,34 @@
 use crate::{
-    instruction::Instruction,
+    balance::Balances,
+    parser::Program,
     record::{StorageChange, StorageOp},
-    traits::MachineComponent,
+    traits::{Invertible, MachineComponent},
 };
 use std::collections::HashMap;
 use z3_ext::ast::{Array, Ast, BV};
+use z3_ext::Sort;
 
+use crate::conversion::{concrete_bytes, keccak256};
 use crate::smt::{ctx, BitVec};
 use crate::{bvc, bvi};
+use rlp::RlpStream;
 
-fn make_storage_arr() {}
-#[derive(Debug, Clone, Default)]
+// Storage of a single account as an SMT array from slot to value, so that reads are resolved
+// by the solver rather than by comparing keys syntactically
+#[derive(Debug, Clone)]
 pub struct AccountStorage {
-    inner: HashMap<BitVec<32>, StorageValue>,
+    // Contents before any write on this path
+    initial: Array<'static>,
+    // `initial` with every write in `writes` stored into it
+    inner: Array<'static>,
+    // Writes in the order they were made
+    writes: Vec<(BitVec<32>, BitVec<32>)>,
     touched: HashMap<BitVec<32>, bool>,
-    code: Option<Vec<Instruction>>,
+    code: Option<Program>,
+    nonce: u64,
 }
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum StorageValue {
-    Array(Array<'static>),
     BV(BitVec<32>),
 }
 
@@ -28,25 +38,123 @@ impl Default for StorageValue {
     }
 }
 
+// A fresh account, e.g. one being deployed, whose slots all hold zero
+impl Default for AccountStorage {
+    fn default() -> Self {
+        let ctx = ctx();
+        let zero = BV::from_u64(ctx, 0, 256);
+        Self::with_initial(Array::const_array(ctx, &Sort::bitvector(ctx, 256), &zero))
+    }
+}
+
 impl AccountStorage {
+    pub fn with_initial(initial: Array<'static>) -> Self {
+        Self {
+            inner: initial.clone(),
+            initial,
+            writes: vec![],
+            touched: HashMap::new(),
+            code: None,
+            nonce: 0,
+        }
+    }
+
+    // Storage of an account that existed before the analysis; every slot starts out unknown.
+    // It is assumed to be a contract that has not created any other (EIP-161).
+    pub fn symbolic(addr: &Address) -> Self {
+        let ctx = ctx();
+        let slot = Sort::bitvector(ctx, 256);
+        let name = format!("storage_{}", addr.as_ref());
+        let mut account = Self::with_initial(Array::new_const(ctx, name, &slot, &slot));
+        account.nonce = 1;
+        account
+    }
+
+    pub fn code(&self) -> Option<&Program> {
+        self.code.as_ref()
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
     pub fn sstore(&mut self, index: BitVec<32>, val: StorageValue) {
-        self.inner.insert(index, val);
+        let StorageValue::BV(val) = val;
+        self.inner = self.inner.store(index.as_ref(), val.as_ref());
+        self.writes.push((index, val));
     }
 
     pub fn sload(&self, index: &BitVec<32>) -> StorageValue {
-        if let Some(val) = self.inner.get(index) {
-            val.clone()
-        } else {
-            Default::default()
+        let val = self.inner.select(index.as_ref()).as_bv().unwrap().simplify();
+        StorageValue::BV(BitVec::with_bv(val))
+    }
+
+    // Value of the slot before any write on this path
+    pub fn original(&self, index: &BitVec<32>) -> BitVec<32> {
+        let val = self.initial.select(index.as_ref()).as_bv().unwrap().simplify();
+        BitVec::with_bv(val)
+    }
+
+    // Whether the slot was read or written on this path. Symbolic keys only match keys built
+    // from the same term.
+    pub fn is_warm(&self, index: &BitVec<32>) -> bool {
+        self.touched.contains_key(index)
+    }
+
+    // Ends the transaction: the current contents become the original ones of the next
+    // transaction, and every slot is cold again
+    pub fn commit(&mut self) {
+        self.initial = self.inner.clone();
+        self.writes.clear();
+        self.touched.clear();
+    }
+
+    // Drops every write after the first `len`
+    fn truncate_writes(&mut self, len: usize) {
+        if len >= self.writes.len() {
+            return;
         }
+        self.writes.truncate(len);
+        self.inner = self
+            .writes
+            .iter()
+            .fold(self.initial.clone(), |arr, (idx, val)| {
+                arr.store(idx.as_ref(), val.as_ref())
+            });
     }
 }
 
 pub type Address = BitVec<20>;
 
+// Address of a contract created with CREATE: keccak256(rlp([sender, nonce]))[12..]
+pub fn create_address(sender: &[u8], nonce: u64) -> Address {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&sender).append(&nonce);
+    address_from_hash(keccak256(&stream.out()))
+}
+
+// Address of a contract created with CREATE2: keccak256(0xff ++ sender ++ salt ++ keccak256(init_code))[12..]
+pub fn create2_address(sender: &[u8], salt: &[u8], init_code: &[u8]) -> Address {
+    let mut preimage = vec![0xff];
+    preimage.extend_from_slice(sender);
+    preimage.extend_from_slice(salt);
+    preimage.extend_from_slice(&keccak256(init_code));
+    address_from_hash(keccak256(&preimage))
+}
+
+fn address_from_hash(hash: [u8; 32]) -> Address {
+    let mut addr = [0u8; 20];
+    addr.copy_from_slice(&hash[12..]);
+    addr.into()
+}
+
+// The world state along a path: storage, code and nonce of every account the path has
+// touched, and the balance of every account. Accounts that are absent existed before the
+// analysis, so their storage is unknown.
 #[derive(Debug, Clone, Default)]
 pub struct GlobalStorage {
     inner: HashMap<Address, AccountStorage>,
+    pub balances: Balances,
 }
 
 impl GlobalStorage {
@@ -59,7 +167,9 @@ impl GlobalStorage {
     }
 
     pub fn get(&self, addr: &Address) -> AccountStorage {
-        self.inner.get(addr).cloned().unwrap_or_default()
+        self.account(addr)
+            .cloned()
+            .unwrap_or_else(|| AccountStorage::symbolic(addr))
     }
     pub fn new() -> Self {
         Default::default()
@@ -68,12 +178,81 @@ impl GlobalStorage {
         self.inner.insert(addr, Default::default());
         self
     }
-    pub fn with_contract(mut self, addr: Address, pgm: Vec<Instruction>) -> Self {
-        let mut account = AccountStorage::default();
-        account.code = Some(pgm);
-        self.inner.insert(addr, account);
+    pub fn with_contract(mut self, addr: Address, pgm: Program) -> Self {
+        self.create_account(&addr, Some(pgm));
         self
     }
+
+    // The account at `addr`, if the path has touched it
+    pub fn account(&self, addr: &Address) -> Option<&AccountStorage> {
+        self.inner.get(addr)
+    }
+
+    pub fn account_mut(&mut self, addr: &Address) -> &mut AccountStorage {
+        self.inner
+            .entry(addr.clone())
+            .or_insert_with(|| AccountStorage::symbolic(addr))
+    }
+
+    // Puts back an account as returned by `account`, removing it if it was absent
+    pub fn restore_account(&mut self, addr: &Address, account: Option<AccountStorage>) {
+        match account {
+            Some(account) => self.inner.insert(addr.clone(), account),
+            None => self.inner.remove(addr),
+        };
+    }
+
+    // A new contract at `addr`, with empty storage and the given runtime code (EIP-161)
+    pub fn create_account(&mut self, addr: &Address, code: Option<Program>) {
+        let mut account = AccountStorage::default();
+        account.code = code;
+        account.nonce = 1;
+        self.inner.insert(addr.clone(), account);
+    }
+
+    pub fn sload(&self, addr: &Address, idx: &BitVec<32>) -> StorageValue {
+        self.read(addr, |account| account.sload(idx))
+    }
+
+    pub fn original(&self, addr: &Address, idx: &BitVec<32>) -> BitVec<32> {
+        self.read(addr, |account| account.original(idx))
+    }
+
+    pub fn is_warm(&self, addr: &Address, idx: &BitVec<32>) -> bool {
+        self.account(addr)
+            .map(|account| account.is_warm(idx))
+            .unwrap_or(false)
+    }
+
+    // Code of the account at `addr`, if it is known on this path
+    pub fn code(&self, addr: &Address) -> Option<&Program> {
+        self.account(addr)?.code()
+    }
+
+    pub fn set_code(&mut self, addr: &Address, code: Option<Program>) {
+        self.account_mut(addr).code = code;
+    }
+
+    pub fn nonce(&self, addr: &Address) -> u64 {
+        self.read(addr, |account| account.nonce)
+    }
+
+    pub fn set_nonce(&mut self, addr: &Address, nonce: u64) {
+        self.account_mut(addr).nonce = nonce;
+    }
+
+    // Ends the transaction for every account the path has touched. Balances carry over as
+    // they are.
+    pub fn commit(&mut self) {
+        self.inner.values_mut().for_each(AccountStorage::commit);
+    }
+
+    fn read<T>(&self, addr: &Address, f: impl FnOnce(&AccountStorage) -> T) -> T {
+        match self.account(addr) {
+            Some(account) => f(account),
+            None => f(&AccountStorage::symbolic(addr)),
+        }
+    }
 }
 
 impl MachineComponent for GlobalStorage {
@@ -112,6 +291,12 @@ impl MachineComponent for GlobalStorage {
                         val: val.clone(),
                     }]);
             }
+            crate::record::StorageOp::Restore { addr, .. } => {
+                addr_record_map
+                    .entry(addr)
+                    .or_insert_with(Vec::new)
+                    .push(op.clone());
+            }
         });
         addr_record_map
             .into_iter()
@@ -119,15 +304,30 @@ impl MachineComponent for GlobalStorage {
                 let change = StorageChange {
                     log: storage_ops_log,
                 };
-                self.inner
-                    .entry(address.clone())
-                    .and_modify(|account| account.apply_change(change.clone()))
-                    .or_insert_with(|| {
-                        let mut new_acc = AccountStorage::default();
-                        new_acc.apply_change(change);
-                        new_acc
-                    });
+                self.account_mut(address).apply_change(change);
+            })
+    }
+}
+
+impl Invertible for GlobalStorage {
+    fn inverse_of(&self, rec: &Self::Record) -> Self::Record {
+        let log = rec
+            .log
+            .iter()
+            .flat_map(|op| {
+                let addr = match op {
+                    StorageOp::Read { addr, .. }
+                    | StorageOp::Write { addr, .. }
+                    | StorageOp::Restore { addr, .. } => addr,
+                };
+                let change = StorageChange {
+                    log: vec![op.clone()],
+                };
+                // An absent account has no writes and no warm slots
+                self.read(addr, |account| account.inverse_of(&change).log)
             })
+            .collect::<Vec<_>>();
+        StorageChange { log }
     }
 }
 
@@ -141,12 +341,49 @@ impl MachineComponent for AccountStorage {
             }
             crate::record::StorageOp::Write { addr, idx, val } => {
                 self.touched.insert(idx.clone(), true);
-                self.inner.insert(idx, StorageValue::BV(val));
+                self.sstore(idx, StorageValue::BV(val));
+            }
+            crate::record::StorageOp::Restore {
+                addr,
+                idx,
+                writes,
+                touched,
+            } => {
+                if touched {
+                    self.touched.insert(idx.clone(), true);
+                } else {
+                    self.touched.remove(&idx);
+                }
+                self.truncate_writes(writes);
             }
         })
     }
 }
 
+impl Invertible for AccountStorage {
+    fn inverse_of(&self, rec: &Self::Record) -> Self::Record {
+        // Every restore holds the state from before the record, so their order does not matter
+        let log = rec
+            .log
+            .iter()
+            .map(|op| {
+                let (addr, idx) = match op {
+                    StorageOp::Read { addr, idx }
+                    | StorageOp::Write { addr, idx, .. }
+                    | StorageOp::Restore { addr, idx, .. } => (addr, idx),
+                };
+                StorageOp::Restore {
+                    addr: addr.clone(),
+                    idx: idx.clone(),
+                    writes: self.writes.len(),
+                    touched: self.touched.contains_key(idx),
+                }
+            })
+            .collect::<Vec<_>>();
+        StorageChange { log }
+    }
+}
+
 #[test]
 fn test_basic_lookup_works_in_acc_storage() {
     let addr = Address::new_const("Address1");
@@ -178,11 +415,82 @@ fn test_basic_lookup_global_storage() {
 #[test]
 fn test_storage_with_solidity_mapping() {}
 
-// Storage keys must be concrete
-// However, values can be symbolic
-// In the case of compound structures like mappings, the following
+#[test]
+fn test_global_storage_writes_and_undoes_per_account() {
+    let (a, b) = (Address::new_literal(1), Address::new_literal(2));
+    let mut world = GlobalStorage::new();
+    world.create_account(&a, None);
+    let write = |addr: &Address, val: u64| StorageOp::Write {
+        addr: addr.clone(),
+        idx: bvi(0),
+        val: BitVec::new_literal(val),
+    };
+    let change = StorageChange {
+        log: vec![write(&a, 7), write(&b, 8)],
+    };
+    let inverse = world.inverse_of(&change);
+    world.apply_change(change);
+    assert_eq!(StorageValue::BV(bvi(7)), world.sload(&a, &bvi(0)));
+    assert_eq!(StorageValue::BV(bvi(8)), world.sload(&b, &bvi(0)));
+
+    world.apply_change(inverse);
+    assert_eq!(StorageValue::BV(bvi(0)), world.sload(&a, &bvi(0)));
+    assert!(!world.is_warm(&b, &bvi(0)));
+}
+
+#[test]
+fn test_equal_keys_read_the_same_slot() {
+    use z3_ext::{SatResult, Solver};
+    let mut acc_store = AccountStorage::symbolic(&Address::new_const("Address1"));
+    let key_a: BitVec<32> = bvc("key_a");
+    let key_b: BitVec<32> = bvc("key_b");
+    acc_store.sstore(key_a.clone(), StorageValue::BV(bvi(7)));
+
+    // Whenever the keys are equal, reading through the other key sees the write
+    let StorageValue::BV(read) = acc_store.sload(&key_b);
+    let solver = Solver::new(ctx());
+    solver.assert(&key_a.as_ref()._eq(key_b.as_ref()));
+    solver.assert(&read.as_ref()._eq(bvi::<32>(7).as_ref()).not());
+    assert_eq!(SatResult::Unsat, solver.check());
+}
+
+#[test]
+fn test_initial_storage() {
+    let fresh = AccountStorage::default();
+    assert_eq!(fresh.sload(&bvc("slot")), StorageValue::BV(bvi(0)));
+
+    let existing = AccountStorage::symbolic(&Address::new_const("Address1"));
+    let StorageValue::BV(read) = existing.sload(&bvi(0));
+    assert!(read.as_ref().as_u64().is_none());
+}
+
+#[test]
+fn test_create_address() {
+    let sender = hex::decode("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0").unwrap();
+    let addr = create_address(&sender, 0);
+    assert_eq!(
+        "cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d",
+        hex::encode(concrete_bytes(addr.as_ref()).unwrap())
+    );
+    let addr = create_address(&sender, 1);
+    assert_eq!(
+        "343c43a37d37dff08ae8c4a11544c718abb4fcf8",
+        hex::encode(concrete_bytes(addr.as_ref()).unwrap())
+    );
+}
+
+#[test]
+fn test_create2_address() {
+    // First example from EIP-1014
+    let addr = create2_address(&[0u8; 20], &[0u8; 32], &[0u8]);
+    assert_eq!(
+        "4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38",
+        hex::encode(concrete_bytes(addr.as_ref()).unwrap())
+    );
+}
+
+// Both storage keys and values can be symbolic
 // Global Storage:
 // HashMap(Address -> AccountStorage)
-// AccountStorage(BitVec<32> -> StorageValue)
-// StorageValue(ConcreteBytes<32> OR SymbolicBytes<32>)
+// AccountStorage(Array BitVec<32> -> BitVec<32>)
 //
@@ -10,7 +10,7 @@ use crate::smt::BitVec;
 use crate::stack::Stack;
 use crate::state::context::ExecutionEnv;
 use crate::state::evm::EvmState;
-use crate::storage::{AccountStorage, StorageValue};
+use crate::storage::{GlobalStorage, StorageValue};
 use uuid::Uuid;
 use z3_ext::ast::Bool;
 
@@ -38,7 +38,7 @@ pub trait MachineState<const STACK_ITEM_SZ: usize> {
     fn mem_read(&self, idx: Index) -> BitVec<32>;
     fn stack_apply(&mut self, stack_rec: StackChange<STACK_ITEM_SZ>);
     fn mem_apply(&mut self, mem_rec: MemChange);
-    fn storage(&self) -> &AccountStorage;
+    fn storage(&self) -> &GlobalStorage;
     fn storage_write(&mut self, idx: Index, val: StorageValue);
     fn storage_read(&self, idx: &Index) -> StorageValue;
     fn storage_apply(&mut self, storage_rec: StorageChange);
@@ -57,10 +57,15 @@ pub trait Machine<const STACK_ITEM_SZ: usize> {
 
 pub trait MachineInstruction<'ctx, const SZ: usize> {
     type Error;
-    fn exec(&self, mach: &EvmState, env: &ExecutionEnv) -> MachineRecord<SZ>;
+    fn exec(&self, mach: &EvmState, env: &ExecutionEnv) -> Result<MachineRecord<SZ>, Self::Error>;
 }
 
 pub trait MachineComponent {
     type Record;
     fn apply_change(&mut self, rec: Self::Record);
 }
+
+pub trait Invertible: MachineComponent {
+    // The record that, applied right after `rec`, restores the current state
+    fn inverse_of(&self, rec: &Self::Record) -> Self::Record;
+}
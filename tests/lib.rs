@@ -1,7 +1,8 @@
 #![allow(unused, unused_imports)]
 extern crate ser;
 use ser::{
-    bvc, bvi, conversion::*, machine::*, memory::*, parser::*, stack::*, storage::*, traits::*,
+    bvc, bvi, conversion::*, machine::*, memory::*, parser::*, stack::*, state::context::*,
+    smt::BitVec, state::revert::*, storage::*, traits::*,
 };
 use z3::{ast::*, SatResult};
 
@@ -142,3 +143,993 @@ fn test_swap2_jumpi_maybe_revert() {
         reachability_report.get(1).unwrap().1.unwrap()
     );
 }
+
+/*
+PUSH1 0x20 // retSize
+PUSH1 0x00 // retOffset
+PUSH1 0x00 // argsSize
+PUSH1 0x00 // argsOffset
+PUSH1 0x00 // value
+PUSH1 0x42 // address
+PUSH2 0x5000 // gas
+CALL
+STOP
+*/
+const SIMPLE_CALL: &str = r#"602060006000600060006042615000f100"#;
+#[test]
+fn test_call_forks_on_success() {
+    let pgm = Parser::with_pgm(SIMPLE_CALL).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    // One leaf in which the call succeeded and one in which it failed
+    assert_eq!(2, leaves.len());
+    leaves.iter().for_each(|leaf| {
+        // The callee may return any amount of data, whatever the requested output size
+        assert_eq!(None, leaf.val.return_data.size().as_ref().simplify().as_u64());
+        assert_eq!(1, leaf.val.calls.len());
+        assert_eq!(1, leaf.val.stack().size());
+    });
+}
+
+#[test]
+fn test_call_fails_past_depth_limit() {
+    use ser::exec::call::MAX_CALL_DEPTH;
+    use ser::exec::Execution;
+    use ser::state::evm::EvmState;
+    let pgm = Parser::with_pgm(SIMPLE_CALL).parse();
+    let mut state = EvmState::with_pgm(pgm.clone());
+    state.depth = MAX_CALL_DEPTH;
+    let mut execution = Execution::new(state, pgm);
+    execution.run(&ExecutionEnv::default());
+    let leaves = execution.states.leaves();
+    // The unknown callee can't succeed, so there is no fork
+    assert_eq!(1, leaves.len());
+    assert_eq!(Some(0), leaves[0].val.stack().peek().unwrap().as_ref().as_u64());
+}
+
+// Like SIMPLE_CALL with an output size of zero, followed by RETURNDATASIZE
+const CALL_RETURNDATASIZE: &str = r#"600060006000600060006042615000f13d00"#;
+#[test]
+fn test_return_data_size_is_independent_of_ret_size() {
+    let pgm = Parser::with_pgm(CALL_RETURNDATASIZE).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(2, leaves.len());
+    leaves.iter().for_each(|leaf| {
+        let size = leaf.val.stack().peek().cloned().unwrap();
+        let solver = z3::Solver::new(ser::smt::ctx());
+        solver.assert(&size.as_ref()._eq(bvi::<32>(64).as_ref()));
+        assert_eq!(SatResult::Sat, solver.check());
+    });
+}
+
+/*
+PUSH1 0x2a
+PUSH1 0x00
+MSTORE
+PUSH1 0x20
+PUSH1 0x00
+RETURN
+*/
+const RETURN_42: &str = r#"602a60005260206000f3"#;
+#[test]
+fn test_call_executes_registered_callee() {
+    let callee = Parser::with_pgm(RETURN_42).parse();
+    let env = ExecutionEnv::default().set_contract_code(Address::new_literal(0x42), callee);
+    let pgm = Parser::with_pgm(SIMPLE_CALL).parse();
+    let mut evm = Evm::new(pgm, env);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    // The callee has a single path, which returns
+    assert_eq!(1, leaves.len());
+    let leaf = leaves.first().unwrap();
+    assert_eq!(bvi(1), leaf.val.stack().peek().cloned().unwrap());
+    let mut ret_val = leaf.val.mem_read(bvi(0));
+    ret_val.simplify();
+    assert_eq!(bvi(0x2a), ret_val);
+}
+
+// Stores 1 at slot 0
+const SSTORE_ONE: &str = r#"600160005500"#;
+// STATICCALL to 0x42 with no input or output, then STOP
+const SIMPLE_STATICCALL: &str = r#"60006000600060006042615000fa00"#;
+#[test]
+fn test_staticcall_fails_on_state_change() {
+    let callee = Parser::with_pgm(SSTORE_ONE).parse();
+    let env = ExecutionEnv::default().set_contract_code(Address::new_literal(0x42), callee);
+    let pgm = Parser::with_pgm(SIMPLE_STATICCALL).parse();
+    let mut evm = Evm::new(pgm, env);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let leaf = leaves.first().unwrap();
+    // The callee's SSTORE fails its frame, so the call pushes zero
+    assert_eq!(bvi(0), leaf.val.stack().peek().cloned().unwrap());
+}
+
+// Copies the init code 600060005360016000f3 (which returns the single byte 00) from its own
+// code into memory and runs it with CREATE
+const SIMPLE_CREATE: &str = r#"600a600f600039600a60006000f000600060005360016000f3"#;
+#[test]
+fn test_create_deploys_returned_code() {
+    let pgm = Parser::with_pgm(SIMPLE_CREATE).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    // The constructor has a single path, which returns
+    assert_eq!(1, leaves.len());
+    let leaf = leaves.first().unwrap();
+    let world = &leaf.val.world;
+    assert_eq!(2, world.nonce(&Address::default()));
+    let expected = create_address(&[0u8; 20], 1);
+    assert_eq!(1, world.code(&expected).unwrap().bytes.len());
+    let mut pushed = leaf.val.stack().peek().cloned().unwrap();
+    pushed.simplify();
+    let expected: BitVec<32> = expected.as_ref().zero_ext(96).simplify().into();
+    assert_eq!(expected, pushed);
+}
+
+// Like SIMPLE_CREATE, with the init code 60006000fd, which reverts
+const REVERTING_CREATE: &str = r#"6005600f600039600560006000f00060006000fd"#;
+#[test]
+fn test_failed_create_leaves_no_account() {
+    let pgm = Parser::with_pgm(REVERTING_CREATE).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let leaf = leaves.first().unwrap();
+    let world = &leaf.val.world;
+    // The creator's nonce is still used up
+    assert_eq!(2, world.nonce(&Address::default()));
+    assert!(world.account(&create_address(&[0u8; 20], 1)).is_none());
+    assert_eq!(bvi(0), leaf.val.stack().peek().cloned().unwrap());
+}
+
+// Creates a contract whose init code returns the block timestamp as its code
+const SYMBOLIC_CODE_CREATE: &str = r#"6009600f600039600960006000f0004260005260206000f3"#;
+#[test]
+fn test_create_returning_symbolic_code_is_incomplete() {
+    use ser::exec::budget::BudgetExceeded;
+    let pgm = Parser::with_pgm(SYMBOLIC_CODE_CREATE).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let incomplete = evm.exec().incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::Unsupported { pc: 13 }, incomplete[0].1);
+}
+
+// Creates a contract whose constructor returns the single byte 0xef
+const EF_CODE_CREATE: &str = r#"600a600f600039600a60006000f00060ef60005360016000f3"#;
+// Likewise, returning 0xfe
+const FE_CODE_CREATE: &str = r#"600a600f600039600a60006000f00060fe60005360016000f3"#;
+#[test]
+fn test_create_rejects_ef_prefixed_code() {
+    let created = |code: &str| {
+        let mut evm = Evm::with_pgm(Parser::with_pgm(code).parse());
+        let execution = evm.exec();
+        let leaves = execution.states.leaves();
+        assert_eq!(1, leaves.len());
+        let leaf = leaves[0].val.clone();
+        let failed = leaf.stack().peek().unwrap().as_ref()._eq(bvi::<32>(0).as_ref());
+        (failed.simplify().as_bool() == Some(false), leaf.gas.used().as_ref().as_u64().unwrap())
+    };
+    // EIP-3541 rejects the code, which fails the creation and uses up the constructor's gas
+    let (ef_created, ef_used) = created(EF_CODE_CREATE);
+    let (fe_created, fe_used) = created(FE_CODE_CREATE);
+    assert!(!ef_created);
+    assert!(fe_created);
+    assert!(ef_used > fe_used);
+}
+
+// Stores 0xaa at memory[0] and emits it with LOG1 under topic 0x11
+const SIMPLE_LOG: &str = r#"60aa600053601160016000a100"#;
+#[test]
+fn test_log_is_recorded_on_leaf() {
+    let pgm = Parser::with_pgm(SIMPLE_LOG).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let logs = &leaves.first().unwrap().val.logs;
+    assert_eq!(1, logs.len());
+    let log = logs.first().unwrap();
+    assert_eq!(vec![bvi::<32>(0x11)], log.topics.to_vec());
+    assert_eq!(1, log.data.len());
+    assert_eq!(bvi::<1>(0xaa), log.data[0]);
+}
+
+// Returns the single byte 0x2a
+const RETURN_BYTE: &str = r#"602a60005360016000f3"#;
+#[test]
+fn test_return_data_is_leaf_result() {
+    let pgm = Parser::with_pgm(RETURN_BYTE).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let result = leaves.first().unwrap().val.result.clone().unwrap();
+    assert!(result.is_success());
+    assert_eq!(&[bvi::<1>(0x2a)], result.output());
+}
+
+// Reverts with the single byte 0x01 as its payload
+const SIMPLE_REVERT: &str = r#"600160005360016000fd"#;
+#[test]
+fn test_revert_data_is_leaf_result() {
+    let pgm = Parser::with_pgm(SIMPLE_REVERT).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(
+        Some(EvmResult::Revert {
+            data: vec![bvi(1)]
+        }),
+        leaves.first().unwrap().val.result
+    );
+}
+
+#[test]
+fn test_reverts_are_classified() {
+    let pgm = Parser::with_pgm(SIMPLE_REVERT).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let reverts = execution.reverts();
+    assert_eq!(1, reverts.len());
+    // A single byte payload is too short to hold a selector
+    assert_eq!(RevertReason::Unknown, reverts.first().unwrap().1);
+    assert!(execution.reverts_where(|r| r.is_panic()).is_empty());
+}
+
+// Leaves the first word of calldata on the stack
+const LOAD_CALLDATA: &str = r#"60003500"#;
+#[test]
+fn test_falsify_finds_calldata() {
+    let pgm = Parser::with_pgm(LOAD_CALLDATA).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let summary = evm.exec().summary();
+    let cex = summary
+        .falsify(|state| state.stack().peek().unwrap().as_ref()._eq(bvi::<32>(42).as_ref()).not())
+        .expect("the first calldata word can be 42");
+    assert!(cex.calldata.len() >= 32);
+    assert!(cex.calldata[..31].iter().all(|b| *b == 0));
+    assert_eq!(42, cex.calldata[31]);
+
+    // Zero-extending a byte can never exceed 0xff
+    let summary = evm.exec().summary();
+    let cex = summary.falsify(|state| {
+        let byte = state.stack().peek().unwrap().as_ref().extract(7, 0).zero_ext(248);
+        byte.bvule(bvi::<32>(0xff).as_ref())
+    });
+    assert!(cex.is_none());
+}
+
+#[test]
+fn test_rewind_restores_prior_states() {
+    let pgm = Parser::with_pgm(SIMPLE_LOG).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let summary = evm.exec().summary();
+    let (leaf, _) = summary.reachable().first().cloned().unwrap();
+    assert_eq!(8, leaf.history.len());
+
+    // Undo STOP and LOG1
+    let rewound = summary.rewind(2);
+    let (state, _) = rewound.reachable().first().unwrap();
+    assert_eq!(11, state.pc());
+    assert_eq!(3, state.stack().size());
+    assert!(state.logs.is_empty());
+    assert!(!state.halt);
+    assert!(state.result.is_none());
+
+    let mut state = leaf.clone();
+    assert_eq!(8, state.rewind(100));
+    assert_eq!(0, state.pc());
+    assert_eq!(0, state.stack().size());
+    assert_eq!(0, state.mem().size());
+    assert!(!state.undo());
+    // The history is shared with the leaf, which keeps its own
+    assert_eq!(8, leaf.history.len());
+    assert!(leaf.halt);
+}
+
+// Jumps to 13 if the first calldata word is zero. On the fallthrough path it tests the same
+// condition again, so the jump to 15 can never be taken.
+const REDUNDANT_BRANCH: &str = r#"6000358015600d5715600f57005b005b00"#;
+#[test]
+fn test_pruning_drops_infeasible_branches() {
+    let pgm = Parser::with_pgm(REDUNDANT_BRANCH).parse();
+    let mut evm = Evm::with_pgm(pgm.clone());
+    assert_eq!(3, evm.exec().states.leaves().len());
+
+    let options = ser::exec::ExploreOptions {
+        prune_infeasible: true,
+        ..Default::default()
+    };
+    let mut evm = Evm::with_pgm(pgm).with_options(options);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(2, leaves.len());
+    assert!(leaves.iter().all(|leaf| leaf.val.pc() != 16));
+}
+
+#[test]
+fn test_search_strategies_find_the_same_leaves() {
+    use ser::exec::{search::SearchStrategy, ExploreOptions};
+    let pgm = Parser::with_pgm(REDUNDANT_BRANCH).parse();
+    for strategy in [
+        SearchStrategy::DepthFirst,
+        SearchStrategy::BreadthFirst,
+        SearchStrategy::Random { seed: 7 },
+        SearchStrategy::Coverage,
+    ] {
+        let options = ExploreOptions {
+            strategy,
+            ..Default::default()
+        };
+        let mut evm = Evm::with_pgm(pgm.clone()).with_options(options);
+        let mut pcs = evm
+            .exec()
+            .states
+            .leaves()
+            .iter()
+            .map(|leaf| leaf.val.pc())
+            .collect::<Vec<_>>();
+        pcs.sort();
+        assert_eq!(vec![12, 14, 16], pcs);
+    }
+}
+
+// Jumps back to its first instruction forever
+const INFINITE_LOOP: &str = r#"5b600056"#;
+#[test]
+fn test_budget_stops_loops() {
+    use ser::exec::budget::{BudgetExceeded, ExploreBudget};
+    use ser::exec::ExploreOptions;
+    let pgm = Parser::with_pgm(INFINITE_LOOP).parse();
+    let options = ExploreOptions {
+        budget: ExploreBudget {
+            loop_bound: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut evm = Evm::with_pgm(pgm.clone()).with_options(options);
+    let execution = evm.exec();
+    let incomplete = execution.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::LoopBound { pc: 3 }, incomplete[0].1);
+
+    let options = ExploreOptions {
+        budget: ExploreBudget {
+            max_path_steps: Some(10),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut evm = Evm::with_pgm(pgm).with_options(options);
+    let execution = evm.exec();
+    let incomplete = execution.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::PathSteps, incomplete[0].1);
+    assert_eq!(10, incomplete[0].0.val.history.len());
+
+    // Loops are bounded even when no budget is given
+    let mut evm = Evm::with_pgm(Parser::with_pgm(INFINITE_LOOP).parse());
+    let incomplete = evm.exec().incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::LoopBound { pc: 3 }, incomplete[0].1);
+}
+
+#[test]
+fn test_budget_applies_to_nested_frames() {
+    use ser::exec::budget::{BudgetExceeded, ExploreBudget};
+    use ser::exec::ExploreOptions;
+    let callee = Parser::with_pgm(INFINITE_LOOP).parse();
+    let env = ExecutionEnv::default().set_contract_code(Address::new_literal(0x42), callee);
+    let pgm = Parser::with_pgm(SIMPLE_CALL).parse();
+    let options = ExploreOptions {
+        budget: ExploreBudget {
+            loop_bound: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut evm = Evm::new(pgm, env).with_options(options);
+    let execution = evm.exec();
+    // The callee's loop leaves the caller's path incomplete rather than failing the call
+    let incomplete = execution.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::LoopBound { pc: 3 }, incomplete[0].1);
+    assert_eq!(0, incomplete[0].0.val.depth);
+}
+
+// Jumps to the first calldata word, with JUMPDESTs at 4 and 6
+const SYMBOLIC_JUMP: &str = r#"600035565b005b00"#;
+#[test]
+fn test_symbolic_jump_forks_per_jumpdest() {
+    let pgm = Parser::with_pgm(SYMBOLIC_JUMP).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(3, leaves.len());
+    let mut pcs = leaves.iter().map(|leaf| leaf.val.pc()).collect::<Vec<_>>();
+    pcs.sort();
+    assert_eq!(vec![3, 5, 7], pcs);
+    let invalid = leaves.iter().find(|leaf| leaf.val.pc() == 3).unwrap();
+    assert_eq!(Some(EvmResult::InvalidJump), invalid.val.result);
+
+    // The jump costs 8 on top of 6 for the operands, so under a limit of 10 it runs out of gas
+    // rather than forking
+    let pgm = Parser::with_pgm(SYMBOLIC_JUMP).parse();
+    let mut evm = Evm::with_pgm(pgm).with_gas_limit(10);
+    let execution = evm.exec();
+    assert_eq!(1, execution.states.leaves().len());
+    assert_eq!(1, execution.out_of_gas().len());
+}
+
+// Jumps to 4, which is the 0x5b immediate of a PUSH1 rather than a JUMPDEST
+const JUMP_INTO_PUSH_DATA: &str = r#"600456605b00"#;
+#[test]
+fn test_jump_into_push_data_halts() {
+    let pgm = Parser::with_pgm(JUMP_INTO_PUSH_DATA).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(2, leaves[0].val.pc());
+    assert_eq!(Some(EvmResult::InvalidJump), leaves[0].val.result);
+}
+
+// Conditionally jumps to the STOP at 7, which is not a JUMPDEST, on the first calldata word
+const INVALID_JUMPI: &str = r#"6000356007575b00"#;
+#[test]
+fn test_invalid_jumpi_keeps_fallthrough() {
+    let pgm = Parser::with_pgm(INVALID_JUMPI).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let mut results = execution
+        .states
+        .leaves()
+        .iter()
+        .map(|leaf| leaf.val.result.clone())
+        .collect::<Vec<_>>();
+    results.sort_by_key(|result| format!("{:?}", result));
+    assert_eq!(
+        vec![Some(EvmResult::InvalidJump), Some(EvmResult::Stop)],
+        results
+    );
+}
+
+// PUSH1 1, then ADD with a single item on the stack
+const ADD_UNDERFLOW: &str = r#"60010100"#;
+#[test]
+fn test_stack_underflow_halts() {
+    let pgm = Parser::with_pgm(ADD_UNDERFLOW).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(2, leaves[0].val.pc());
+    assert_eq!(Some(EvmResult::StackUnderflow), leaves[0].val.result);
+}
+
+#[test]
+fn test_stack_overflow_halts() {
+    use ser::exec::Execution;
+    use ser::state::evm::EvmState;
+    // DUP1 on a full stack
+    let pgm = Parser::with_pgm("8000").parse();
+    let mut state = EvmState::with_pgm(pgm.clone());
+    (0..MAX_STACK_DEPTH).for_each(|_| state.stack.push(bvi(1)));
+    let mut execution = Execution::new(state, pgm);
+    execution.run(&ExecutionEnv::default());
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(Some(EvmResult::StackOverflow), leaves[0].val.result);
+    assert_eq!(MAX_STACK_DEPTH, leaves[0].val.stack().size());
+}
+
+// BYTE 30 of 0x1234, SIGNEXTEND 0 of 0xff and 2 ** 10
+const BYTE_SIGNEXTEND_EXP: &str = r#"611234601e1a60ff60000b600a60020a00"#;
+#[test]
+fn test_byte_signextend_exp() {
+    let pgm = Parser::with_pgm(BYTE_SIGNEXTEND_EXP).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let stack = leaves[0].val.stack();
+    let item = |n: usize| {
+        let mut item = stack.peek_nth(n).cloned().unwrap();
+        item.simplify();
+        item
+    };
+    assert_eq!(bvi(1024), item(0));
+    let minus_one: BitVec<32> = BV::from_i64(ser::smt::ctx(), -1, 256).into();
+    assert_eq!(minus_one, item(1));
+    assert_eq!(bvi(0x12), item(2));
+}
+
+// 256 ** and 3 ** the first calldata word
+const EXP_256: &str = r#"6000356101000a00"#;
+const EXP_3: &str = r#"60003560030a00"#;
+#[test]
+fn test_symbolic_exponent() {
+    use ser::exec::budget::BudgetExceeded;
+    let pgm = Parser::with_pgm(EXP_256).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    assert!(execution.incomplete().is_empty());
+    // Powers of bases other than powers of two are not modelled
+    let pgm = Parser::with_pgm(EXP_3).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let incomplete = execution.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::Unsupported { pc: 5 }, incomplete[0].1);
+}
+
+// Branches on slot 0, which the program never writes
+const BRANCH_ON_SLOT: &str = r#"600054600757005b00"#;
+// Stores 1 in slot 0 before branching on it
+const BRANCH_ON_WRITTEN_SLOT: &str = r#"6001600055600054600c57005b00"#;
+#[test]
+fn test_initial_storage_is_symbolic() {
+    let options = ser::exec::ExploreOptions {
+        prune_infeasible: true,
+        ..Default::default()
+    };
+    let pgm = Parser::with_pgm(BRANCH_ON_SLOT).parse();
+    let mut evm = Evm::with_pgm(pgm).with_options(options.clone());
+    assert_eq!(2, evm.exec().states.leaves().len());
+
+    let pgm = Parser::with_pgm(BRANCH_ON_WRITTEN_SLOT).parse();
+    let mut evm = Evm::with_pgm(pgm).with_options(options);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(13, leaves[0].val.pc());
+}
+
+// Stores 42 at the offset given by the first calldata word and loads it back
+const SYMBOLIC_MSTORE: &str = r#"602a600035526000355100"#;
+// Copies all of the calldata to memory and reads MSIZE
+const SYMBOLIC_CALLDATACOPY: &str = r#"3660006000375900"#;
+#[test]
+fn test_symbolic_memory_offsets() {
+    let pgm = Parser::with_pgm(SYMBOLIC_MSTORE).parse();
+    let mut evm = Evm::with_pgm(pgm).with_memory_model(MemoryModel::Symbolic);
+    let summary = evm.exec().summary();
+    let cex = summary
+        .falsify(|state| state.stack().peek().unwrap().as_ref()._eq(bvi::<32>(42).as_ref()));
+    assert!(cex.is_none());
+
+    // The concrete model leaves the path incomplete at the MSTORE instead
+    use ser::exec::budget::BudgetExceeded;
+    let mut evm = Evm::with_pgm(Parser::with_pgm(SYMBOLIC_MSTORE).parse());
+    let incomplete = evm.exec().incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::Unsupported { pc: 5 }, incomplete[0].1);
+
+    let pgm = Parser::with_pgm(SYMBOLIC_CALLDATACOPY).parse();
+    let mut evm = Evm::with_pgm(pgm).with_memory_model(MemoryModel::Symbolic);
+    let summary = evm.exec().summary();
+    // Calldata longer than SYMBOLIC_COPY_BOUND leaves a path incomplete at the copy
+    let (leaf, _) = summary
+        .reachable()
+        .iter()
+        .find(|(leaf, _)| leaf.result.as_ref().map_or(false, |result| result.is_success()))
+        .cloned()
+        .unwrap();
+    assert!(leaf.stack().peek().unwrap().as_ref().as_u64().is_none());
+    // Memory grows with the copy, but never past the word holding the last byte copied
+    let cex = summary.falsify(|state| {
+        let size = ser::state::env::call_data_size(0).apply(&[]).as_bv().unwrap();
+        let last_word = size.bvadd(bvi::<32>(31).as_ref());
+        state.stack().peek().unwrap().as_ref().bvule(&last_word)
+    });
+    assert!(cex.is_none());
+}
+
+// Copies the code byte at the offset given by the first calldata word, then loads it
+const SYMBOLIC_CODECOPY: &str = r#"600160003560003960005100"#;
+// Returns as many bytes as the first calldata word
+const SYMBOLIC_RETURN: &str = r#"6000356000f3"#;
+#[test]
+fn test_symbolic_sizes_and_offsets() {
+    use ser::exec::budget::BudgetExceeded;
+    let pgm = Parser::with_pgm(SYMBOLIC_CODECOPY).parse();
+    let mut evm = Evm::with_pgm(pgm).with_memory_model(MemoryModel::Symbolic);
+    let summary = evm.exec().summary();
+    // Some offset reads the CODECOPY opcode itself
+    let cex = summary.falsify(|state| {
+        let word = state.stack().peek().unwrap().as_ref().extract(255, 248);
+        word._eq(bvi::<1>(0x39).as_ref()).not()
+    });
+    assert!(cex.is_some());
+
+    let pgm = Parser::with_pgm(SYMBOLIC_RETURN).parse();
+    let mut evm = Evm::with_pgm(pgm).with_memory_model(MemoryModel::Symbolic);
+    let execution = evm.exec();
+    let incomplete = execution.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::Unsupported { pc: 5 }, incomplete[0].1);
+}
+
+// Copies as many calldata bytes as the first calldata word to memory
+const SYMBOLIC_LENGTH_COPY: &str = r#"600035600060003700"#;
+#[test]
+fn test_copy_past_bound_is_incomplete() {
+    use ser::exec::budget::BudgetExceeded;
+    let pgm = Parser::with_pgm(SYMBOLIC_LENGTH_COPY).parse();
+    let mut evm = Evm::with_pgm(pgm).with_memory_model(MemoryModel::Symbolic);
+    let execution = evm.exec();
+    let incomplete = execution.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!(BudgetExceeded::Unsupported { pc: 7 }, incomplete[0].1);
+    // The copy is only made where the length is within the bound
+    let finished = execution
+        .states
+        .leaves()
+        .into_iter()
+        .filter(|leaf| leaf.val.result == Some(EvmResult::Stop))
+        .count();
+    assert_eq!(1, finished);
+}
+
+// Overwrites the word at 0, then loads it and the untouched word at 0x40 before reading MSIZE
+const MEMORY_EXPANSION: &str = r#"604260005260436000526000516040515900"#;
+#[test]
+fn test_memory_overwrites_and_expands_by_word() {
+    let pgm = Parser::with_pgm(MEMORY_EXPANSION).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    let leaf = &leaves.first().unwrap().val;
+    let stack = leaf.stack();
+    assert_eq!(bvi::<32>(0x60), *stack.peek_nth(0).unwrap());
+    assert_eq!(bvi::<32>(0), *stack.peek_nth(1).unwrap());
+    assert_eq!(bvi::<32>(0x43), *stack.peek_nth(2).unwrap());
+    assert_eq!(0x60, leaf.mem().m_size());
+    assert_eq!(Some(3), leaf.mem().words_after(&bvi(0), &bvi(0)));
+    assert_eq!(Some(4), leaf.mem().words_after(&bvi(0x61), &bvi(1)));
+}
+
+// Stores 7 at the hash of the first calldata word and 9 at slot 0, then loads the hashed slot
+const HASHED_SLOT: &str = r#"6000356000526020600020806007905560096000555400"#;
+#[test]
+fn test_hashed_slot_does_not_alias_small_slots() {
+    let pgm = Parser::with_pgm(HASHED_SLOT).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let summary = evm.exec().summary();
+    let cex = summary
+        .falsify(|state| state.stack().peek().unwrap().as_ref()._eq(bvi::<32>(7).as_ref()));
+    assert!(cex.is_none());
+}
+
+#[test]
+fn test_gas_accounting() {
+    // Four pushes, an MSTORE expanding memory by a word, and RETURN
+    let pgm = Parser::with_pgm(SUPERSIMPLE).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaf = execution.states.leaves().first().unwrap().val.clone();
+    assert_eq!(bvi::<32>(18), *leaf.gas.used());
+
+    // GAS pushes what is left after paying for itself
+    let pgm = Parser::with_pgm("5a00").parse();
+    let mut evm = Evm::with_pgm(pgm).with_gas_limit(1000);
+    let execution = evm.exec();
+    let leaf = execution.states.leaves().first().unwrap().val.clone();
+    assert_eq!(bvi::<32>(998), *leaf.stack().peek().unwrap());
+}
+
+// Raises 2 to the first calldata word, which costs 50 gas per byte of the exponent
+const SYMBOLIC_EXP: &str = r#"60003560020a00"#;
+// Copies 2^40 - 1 bytes of calldata to memory
+const HUGE_COPY: &str = r#"64ffffffffff600060003700"#;
+#[test]
+fn test_out_of_gas() {
+    let pgm = Parser::with_pgm(INFINITE_LOOP).parse();
+    let mut evm = Evm::with_pgm(pgm).with_gas_limit(100);
+    let execution = evm.exec();
+    let out_of_gas = execution.out_of_gas();
+    assert_eq!(1, out_of_gas.len());
+    assert_eq!(bvi::<32>(100), *out_of_gas[0].val.gas.used());
+
+    // Small exponents fit in the limit and large ones don't
+    let pgm = Parser::with_pgm(SYMBOLIC_EXP).parse();
+    let mut evm = Evm::with_pgm(pgm).with_gas_limit(100);
+    let execution = evm.exec();
+    assert_eq!(1, execution.out_of_gas().len());
+    assert_eq!(2, execution.states.leaves().len());
+
+    // A huge copy runs out of gas before any of its bytes are built
+    let pgm = Parser::with_pgm(HUGE_COPY).parse();
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    assert_eq!(1, execution.out_of_gas().len());
+    assert_eq!(1, execution.states.leaves().len());
+}
+
+// Dispatches 0xaabbccdd to a function setting slot 0 to 1, and 0x11223344 to one that stops
+const GAS_DISPATCHER: &str =
+    r#"60003560e01c8063aabbccdd14601a57631122334414602157005b6001600055005b00"#;
+#[test]
+fn test_gas_bounds_per_selector() {
+    use ruint::aliases::U256;
+    let pgm = Parser::with_pgm(GAS_DISPATCHER).parse();
+    assert_eq!(
+        vec![[0xaa, 0xbb, 0xcc, 0xdd], [0x11, 0x22, 0x33, 0x44]],
+        pgm.selectors()
+    );
+    let mut evm = Evm::with_pgm(pgm);
+    let bounds = evm.exec().gas_bounds();
+    assert_eq!(2, bounds.len());
+
+    // The cold SSTORE costs 2200 if slot 0 already holds 1, and 22100 if it holds 0
+    let set = &bounds[0];
+    assert_eq!(U256::from(2241), set.min);
+    assert_eq!(U256::from(22141), set.max);
+    assert_eq!(vec![0xaa, 0xbb, 0xcc, 0xdd], set.max_witness.calldata[..4].to_vec());
+    assert!(!set.out_of_gas);
+
+    let stop = &bounds[1];
+    assert_eq!(U256::from(54), stop.min);
+    assert_eq!(U256::from(54), stop.max);
+}
+
+#[test]
+fn test_gas_bounds_with_concrete_calldata() {
+    let pgm = Parser::with_pgm(GAS_DISPATCHER).parse();
+    let env = ExecutionEnv::default().set_calldata("aabbccdd");
+    let mut evm = Evm::new(pgm, env);
+    let bounds = evm.exec().gas_bounds();
+    // Only the selector the calldata starts with is reachable, and the witness is the calldata
+    assert_eq!(1, bounds.len());
+    assert_eq!([0xaa, 0xbb, 0xcc, 0xdd], bounds[0].selector);
+    assert_eq!(vec![0xaa, 0xbb, 0xcc, 0xdd], bounds[0].max_witness.calldata);
+}
+
+// TIMESTAMP NUMBER CHAINID CALLVALUE BASEFEE
+const BLOCK_AND_TX_FIELDS: &str = r#"42434634480000"#;
+#[test]
+fn test_environment_opcodes_read_the_env() {
+    let pgm = Parser::with_pgm(BLOCK_AND_TX_FIELDS).parse();
+    let env = ExecutionEnv::default()
+        .set_timestamp(bvi(1_700_000_000))
+        .set_number(bvi(17))
+        .set_chain_id(bvi(1))
+        .set_callvalue(bvi(5))
+        .set_base_fee(bvi(7));
+    let mut evm = Evm::new(pgm.clone(), env);
+    let execution = evm.exec();
+    let leaf = execution.states.leaves()[0].val.clone();
+    let stack = leaf.stack();
+    assert_eq!(bvi::<32>(7), *stack.peek_nth(0).unwrap());
+    assert_eq!(bvi::<32>(5), *stack.peek_nth(1).unwrap());
+    assert_eq!(bvi::<32>(1), *stack.peek_nth(2).unwrap());
+    assert_eq!(bvi::<32>(17), *stack.peek_nth(3).unwrap());
+    assert_eq!(bvi::<32>(1_700_000_000), *stack.peek_nth(4).unwrap());
+
+    // Fields that are not configured are named symbolic values
+    let mut evm = Evm::with_pgm(pgm);
+    let execution = evm.exec();
+    let leaf = execution.states.leaves()[0].val.clone();
+    let timestamp = leaf.stack().peek_nth(4).unwrap().as_ref().simplify();
+    assert!(timestamp.as_u64().is_none());
+    assert!(timestamp.to_string().contains("timestamp"));
+}
+
+// Pushes its own balance, then the caller's
+const SELF_AND_CALLER_BALANCE: &str = r#"47333100"#;
+#[test]
+fn test_callvalue_is_credited() {
+    let caller = Address::new_literal(0xca);
+    let env = ExecutionEnv::default()
+        .set_caller(caller.clone())
+        .set_callvalue(bvi(3))
+        .set_balance(caller, 10u64)
+        .set_balance(Address::default(), 5u64);
+    let pgm = Parser::with_pgm(SELF_AND_CALLER_BALANCE).parse();
+    let mut evm = Evm::new(pgm, env);
+    let execution = evm.exec();
+    let leaf = execution.states.leaves()[0].val.clone();
+    assert_eq!(bvi::<32>(7), *leaf.stack().peek_nth(0).unwrap());
+    assert_eq!(bvi::<32>(8), *leaf.stack().peek_nth(1).unwrap());
+}
+
+// Like SIMPLE_CALL, but sends 5 wei
+const VALUE_CALL: &str = r#"602060006000600060056042615000f100"#;
+#[test]
+fn test_call_transfers_value() {
+    let callee = Address::new_literal(0x42);
+    let env = ExecutionEnv::default()
+        .set_contract_code(callee.clone(), Parser::with_pgm(RETURN_42).parse())
+        .set_callvalue(bvi(0))
+        .set_balance(callee.clone(), 1u64);
+    let pgm = Parser::with_pgm(VALUE_CALL).parse();
+
+    let mut evm = Evm::new(pgm.clone(), env.clone().set_balance(Address::default(), 8u64));
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let balances = &leaves[0].val.world.balances;
+    assert_eq!(bvi::<32>(3), balances.balance(&Address::default()));
+    assert_eq!(bvi::<32>(6), balances.balance(&callee));
+
+    // Without enough balance the call fails and nothing moves
+    let mut evm = Evm::new(pgm.clone(), env.clone().set_balance(Address::default(), 4u64));
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(bvi::<32>(0), *leaves[0].val.stack().peek().unwrap());
+    assert_eq!(bvi::<32>(4), leaves[0].val.world.balances.balance(&Address::default()));
+
+    // An unknown balance may or may not cover the value
+    let mut evm = Evm::new(pgm, env);
+    let execution = evm.exec();
+    let mut flags = execution
+        .states
+        .leaves()
+        .iter()
+        .map(|leaf| leaf.val.stack().peek().unwrap().as_ref().as_u64().unwrap())
+        .collect::<Vec<_>>();
+    flags.sort();
+    assert_eq!(vec![0, 1], flags);
+}
+
+// Like VALUE_CALL, but to 0x43, which has no code
+const VALUE_CALL_TO_EMPTY: &str = r#"602060006000600060056043615000f100"#;
+#[test]
+fn test_value_call_to_empty_account_pays_for_it() {
+    let callee = Address::new_literal(0x43);
+    let pgm = Parser::with_pgm(VALUE_CALL_TO_EMPTY).parse();
+    let used = |callee_balance: u64| {
+        let env = ExecutionEnv::default()
+            .set_balance(Address::default(), 8u64)
+            .set_balance(callee.clone(), callee_balance);
+        let mut evm = Evm::new(pgm.clone(), env);
+        let execution = evm.exec();
+        let leaf = execution.states.leaves()[0].val.clone();
+        leaf.gas.used().as_ref().as_u64().unwrap()
+    };
+    // An account with no code, nonce or balance is brought into existence by the value
+    assert_eq!(ser::gas::G_NEW_ACCOUNT, used(0) - used(1));
+}
+
+// Sends the contract's whole balance to the caller
+const SEND_BALANCE_TO_CALLER: &str = r#"600060006000600047335af100"#;
+// Sends the callvalue back to the caller
+const SEND_CALLVALUE_TO_CALLER: &str = r#"600060006000600034335af100"#;
+#[test]
+fn test_falsify_caller_profit() {
+    use ser::state::evm::EvmState;
+    let attacker = Address::new_literal(0xca);
+    let no_profit = |state: &EvmState| {
+        let balances = &state.world.balances;
+        let (after, before) = (balances.balance(&attacker), balances.initial(&attacker));
+        after.as_ref().bvule(before.as_ref())
+    };
+    for (code, profits) in [(SEND_BALANCE_TO_CALLER, true), (SEND_CALLVALUE_TO_CALLER, false)] {
+        let env = ExecutionEnv::default().set_caller(attacker.clone());
+        let mut evm = Evm::new(Parser::with_pgm(code).parse(), env);
+        let execution = evm.exec();
+        let counterexample = execution.summary().falsify(&no_profit);
+        assert_eq!(profits, counterexample.is_some());
+    }
+}
+
+// Sets slot 0 of its own storage to 1
+const STORE_ONE: &str = r#"600160005500"#;
+// Calls 0x42 with all its gas, then pushes the callee's code size and code hash
+const CALL_THEN_INSPECT: &str = r#"6020600060006000600060425af160423b60423f00"#;
+#[test]
+fn test_world_state_tracks_every_account() {
+    let callee = Address::new_literal(0x42);
+    let env = ExecutionEnv::default()
+        .set_contract_code(callee.clone(), Parser::with_pgm(STORE_ONE).parse());
+    let mut evm = Evm::new(Parser::with_pgm(CALL_THEN_INSPECT).parse(), env);
+    let execution = evm.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    let leaf = &leaves[0].val;
+
+    // The callee wrote its own storage, not the caller's
+    let StorageValue::BV(written) = leaf.world.sload(&callee, &bvi(0));
+    assert_eq!(bvi::<32>(1), written);
+    let StorageValue::BV(untouched) = leaf.world.sload(&leaf.address, &bvi(0));
+    assert!(untouched.as_ref().as_u64().is_none());
+
+    let hash: BitVec<32> = keccak256(&hex::decode(STORE_ONE).unwrap()).into();
+    assert_eq!(hash, *leaf.stack().peek().unwrap());
+    assert_eq!(bvi::<32>(6), *leaf.stack().peek_nth(1).unwrap());
+}
+
+// Increments slot 0 of its own storage
+const INCREMENT: &str = r#"60005460010160005500"#;
+// Copies INCREMENT, which follows it, into memory and returns it as the runtime code
+const DEPLOY_INCREMENT: &str = r#"600a600c600039600a6000f360005460010160005500"#;
+#[test]
+fn test_sequence_carries_storage_across_transactions() {
+    let evm = Evm::with_pgm(Parser::with_pgm(DEPLOY_INCREMENT).parse());
+    let below_two = |state: &ser::state::evm::EvmState| {
+        let StorageValue::BV(count) = state.world.sload(&state.address, &bvi(0));
+        count.as_ref().bvult(bvi::<32>(2).as_ref())
+    };
+
+    // A single call only gets the counter to one
+    let sequence = evm.exec_sequence(1);
+    assert_eq!(1, sequence.paths(0).len());
+    let (deployed, _) = &sequence.paths(0)[0];
+    assert_eq!(
+        hex::decode(INCREMENT).unwrap().len(),
+        deployed.world.code(&deployed.address).unwrap().bytes.len()
+    );
+    assert!(sequence.summary().falsify(below_two).is_none());
+
+    // The second call starts from the storage the first one left behind
+    let sequence = evm.exec_sequence(2);
+    assert_eq!(1, sequence.paths(2).len());
+    let cex = sequence
+        .summary()
+        .falsify(below_two)
+        .expect("two calls get the counter to two");
+    // The constructor and both calls
+    assert_eq!(3, cex.transactions.len());
+    assert_eq!(2, cex.state.transaction);
+}
+
+// Pushes slot 0 of its own storage
+const LOAD_SLOT_ZERO: &str = r#"60005400"#;
+// Stores its 32-byte constructor argument, read from the end of its code, in slot 0 and
+// returns LOAD_SLOT_ZERO, which follows it, as the runtime code
+const DEPLOY_WITH_ARG: &str = r#"6020602038036000396000516000556004601b60003960046000f360005400"#;
+#[test]
+fn test_deploy_then_call() {
+    let init_code = Parser::with_pgm(DEPLOY_WITH_ARG).parse();
+
+    let mut arg = vec![BitVec::<1>::from([0u8; 1]); 31];
+    arg.push(BitVec::from([7u8; 1]));
+    let evm = Evm::with_pgm(init_code.clone().with_args(arg));
+    let deployments = evm.deploy();
+    assert_eq!(1, deployments.len());
+    let deployment = &deployments[0];
+    assert_eq!(hex::decode(LOAD_SLOT_ZERO).unwrap().len(), deployment.code().unwrap().bytes.len());
+
+    // The call starts from the storage the constructor wrote
+    let mut runtime = deployment.call(ExecutionEnv::default()).unwrap();
+    let execution = runtime.exec();
+    let leaves = execution.states.leaves();
+    assert_eq!(1, leaves.len());
+    assert_eq!(bvi::<32>(7), *leaves[0].val.stack().peek().unwrap());
+
+    // A symbolic argument leaves the slot symbolic, and can be read back from a counterexample
+    let arg = (0..32)
+        .map(|i| BitVec::<1>::new_const(format!("arg_{}", i)))
+        .collect::<Vec<_>>();
+    let evm = Evm::with_pgm(init_code.with_args(arg));
+    let deployment = evm.deploy().pop().unwrap();
+    let mut runtime = deployment.call(ExecutionEnv::default()).unwrap();
+    let summary = runtime.exec().summary();
+    let cex = summary
+        .falsify(|state| state.stack().peek().unwrap().as_ref()._eq(bvi::<32>(5).as_ref()).not())
+        .expect("the argument can be 5");
+    assert_eq!(2, cex.transactions.len());
+}
+
+// Returns 32 bytes holding the block timestamp as the runtime code
+const DEPLOY_SYMBOLIC_CODE: &str = r#"4260005260206000f3"#;
+#[test]
+fn test_deploy_reports_symbolic_code() {
+    use ser::exec::budget::BudgetExceeded;
+    let evm = Evm::with_pgm(Parser::with_pgm(DEPLOY_SYMBOLIC_CODE).parse());
+    let deployments = evm.deploy();
+    assert_eq!(1, deployments.len());
+    let deployment = &deployments[0];
+    assert_eq!(Some(BudgetExceeded::Unsupported { pc: 8 }), deployment.incomplete);
+    assert!(deployment.code().is_none());
+    assert!(deployment.call(ExecutionEnv::default()).is_none());
+}